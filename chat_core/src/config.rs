@@ -0,0 +1,26 @@
+use std::fs::File;
+
+use anyhow::{bail, Result};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ServerConfig {
+    pub port: u16,
+    pub db_url: String,
+}
+
+/// Load a yaml config from ./{name}, or /etc/config/{name}, or the path in env `{env}`.
+pub fn load_config<T: DeserializeOwned>(name: &str, env: &str) -> Result<T> {
+    let ret = match (
+        File::open(name),
+        File::open(format!("/etc/config/{}", name)),
+        std::env::var(env),
+    ) {
+        (Ok(file), _, _) => serde_yaml::from_reader(file),
+        (_, Ok(file), _) => serde_yaml::from_reader(file),
+        (_, _, Ok(path)) => serde_yaml::from_reader(File::open(path)?),
+        _ => bail!("No config file found"),
+    };
+
+    Ok(ret?)
+}
@@ -0,0 +1,15 @@
+use serde::{Deserialize, Serialize};
+
+/// Body of error responses.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ErrorOutput {
+    error: String,
+}
+
+impl ErrorOutput {
+    pub fn new(error: impl Into<String>) -> Self {
+        Self {
+            error: error.into(),
+        }
+    }
+}
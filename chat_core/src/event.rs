@@ -0,0 +1,220 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{Chat, MentionKind, Message, Poll};
+
+/// Events sent by the database triggers on the `chat_events` channel, and by clients
+/// connected through websocket.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AppEvent {
+    ChatCreated {
+        chat: Chat,
+    },
+    TopicChanged {
+        chat_id: i64,
+        topic: Option<String>,
+    },
+    MemberAdded {
+        chat_id: i64,
+        user_id: i64,
+    },
+    MemberRemoved {
+        chat_id: i64,
+        user_id: i64,
+    },
+    NewMessage {
+        message: Message,
+        // content is dropped by the trigger when it doesn't fit into a notification
+        #[serde(default)]
+        truncated: bool,
+    },
+    MessageUpdated {
+        message: Message,
+        #[serde(default)]
+        truncated: bool,
+    },
+    MessageDeleted {
+        chat_id: i64,
+        message_id: i64,
+    },
+    MessagePinned {
+        chat_id: i64,
+        message_id: i64,
+        pinned_by: i64,
+    },
+    MessageUnpinned {
+        chat_id: i64,
+        message_id: i64,
+    },
+    ReactionAdded {
+        chat_id: i64,
+        message_id: i64,
+        user_id: i64,
+        emoji: String,
+    },
+    ReactionRemoved {
+        chat_id: i64,
+        message_id: i64,
+        user_id: i64,
+        emoji: String,
+    },
+    // a poll was created or its votes changed
+    PollUpdated {
+        chat_id: i64,
+        message_id: i64,
+        poll: Option<Poll>,
+        // voters are dropped by the trigger when they don't fit into a notification
+        #[serde(default)]
+        truncated: bool,
+    },
+    // sent to the mentioned user only, on top of the message events of the chat
+    Mentioned {
+        chat_id: i64,
+        message_id: i64,
+        sender_id: i64,
+        user_id: i64,
+        kind: MentionKind,
+    },
+    // sent to the user who set the reminder only
+    Reminder {
+        chat_id: i64,
+        user_id: i64,
+        reminder_id: i64,
+        text: String,
+    },
+    // sent by notify_server to followers of a thread
+    ThreadReply {
+        chat_id: i64,
+        parent_id: i64,
+        message_id: i64,
+        sender_id: i64,
+    },
+    Typing {
+        chat_id: i64,
+        user_id: i64,
+    },
+    // sent by notify_server when typing times out or the user sends a message
+    TypingStopped {
+        chat_id: i64,
+        user_id: i64,
+    },
+    MessageRead {
+        chat_id: i64,
+        user_id: i64,
+        message_id: i64,
+    },
+    // sent by notify_server to a reconnecting client which missed events that are no
+    // longer buffered, the client should fetch its state again
+    Resync,
+}
+
+impl AppEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AppEvent::ChatCreated { .. } => "chat_created",
+            AppEvent::TopicChanged { .. } => "topic_changed",
+            AppEvent::MemberAdded { .. } => "member_added",
+            AppEvent::MemberRemoved { .. } => "member_removed",
+            AppEvent::NewMessage { .. } => "new_message",
+            AppEvent::MessageUpdated { .. } => "message_updated",
+            AppEvent::MessageDeleted { .. } => "message_deleted",
+            AppEvent::MessagePinned { .. } => "message_pinned",
+            AppEvent::MessageUnpinned { .. } => "message_unpinned",
+            AppEvent::ReactionAdded { .. } => "reaction_added",
+            AppEvent::ReactionRemoved { .. } => "reaction_removed",
+            AppEvent::PollUpdated { .. } => "poll_updated",
+            AppEvent::Mentioned { .. } => "mentioned",
+            AppEvent::Reminder { .. } => "reminder",
+            AppEvent::ThreadReply { .. } => "thread_reply",
+            AppEvent::Typing { .. } => "typing",
+            AppEvent::TypingStopped { .. } => "typing_stopped",
+            AppEvent::MessageRead { .. } => "message_read",
+            AppEvent::Resync => "resync",
+        }
+    }
+
+    /// Chat the event happened in, none for events about the connection.
+    pub fn chat_id(&self) -> Option<i64> {
+        let chat_id = match self {
+            AppEvent::ChatCreated { chat } => chat.id,
+            AppEvent::TopicChanged { chat_id, .. } => *chat_id,
+            AppEvent::MemberAdded { chat_id, .. } => *chat_id,
+            AppEvent::MemberRemoved { chat_id, .. } => *chat_id,
+            AppEvent::NewMessage { message, .. } => message.chat_id,
+            AppEvent::MessageUpdated { message, .. } => message.chat_id,
+            AppEvent::MessageDeleted { chat_id, .. } => *chat_id,
+            AppEvent::MessagePinned { chat_id, .. } => *chat_id,
+            AppEvent::MessageUnpinned { chat_id, .. } => *chat_id,
+            AppEvent::ReactionAdded { chat_id, .. } => *chat_id,
+            AppEvent::ReactionRemoved { chat_id, .. } => *chat_id,
+            AppEvent::PollUpdated { chat_id, .. } => *chat_id,
+            AppEvent::Mentioned { chat_id, .. } => *chat_id,
+            AppEvent::Reminder { chat_id, .. } => *chat_id,
+            AppEvent::ThreadReply { chat_id, .. } => *chat_id,
+            AppEvent::Typing { chat_id, .. } => *chat_id,
+            AppEvent::TypingStopped { chat_id, .. } => *chat_id,
+            AppEvent::MessageRead { chat_id, .. } => *chat_id,
+            AppEvent::Resync => return None,
+        };
+        Some(chat_id)
+    }
+
+    /// Ephemeral events are not worth replaying to reconnecting clients.
+    pub fn is_ephemeral(&self) -> bool {
+        matches!(
+            self,
+            AppEvent::Typing { .. } | AppEvent::TypingStopped { .. } | AppEvent::Resync
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ChatType;
+    use anyhow::Result;
+
+    #[test]
+    fn app_event_should_parse_trigger_payload() -> Result<()> {
+        let payload = r#"{"event" : "chat_created", "chat" : {"id":5,"name":"x","type":"public_channel","created_at":"2024-08-15T08:00:00.706676+00:00","ws_id":1}}"#;
+        let event: AppEvent = serde_json::from_str(payload)?;
+        assert_eq!(event.name(), "chat_created");
+        match event {
+            AppEvent::ChatCreated { chat } => {
+                assert_eq!(chat.id, 5);
+                assert_eq!(chat.r#type, ChatType::PublicChannel);
+                // the api keeps sending the variant names
+                assert_eq!(serde_json::to_value(&chat.r#type)?, "PublicChannel");
+                assert_eq!(chat.max_pins, 50);
+            }
+            _ => panic!("unexpected event: {:?}", event),
+        }
+
+        let payload = r#"{"event" : "member_removed", "chat_id" : 5, "user_id" : 3}"#;
+        let event: AppEvent = serde_json::from_str(payload)?;
+        assert_eq!(
+            event,
+            AppEvent::MemberRemoved {
+                chat_id: 5,
+                user_id: 3
+            }
+        );
+
+        let payload = r#"{"event" : "message_deleted", "chat_id" : 5, "message_id" : 2}"#;
+        let event: AppEvent = serde_json::from_str(payload)?;
+        assert_eq!(event.chat_id(), Some(5));
+        assert_eq!(event.name(), "message_deleted");
+
+        let payload = r#"{"event" : "new_message", "message" : {"id": 2, "images": [], "chat_id": 5, "content": "", "sender_id": 1, "created_at": "2024-08-15T08:00:00.712737+00:00"}, "truncated" : true}"#;
+        let event: AppEvent = serde_json::from_str(payload)?;
+        assert!(matches!(
+            event,
+            AppEvent::NewMessage {
+                truncated: true,
+                ..
+            }
+        ));
+
+        Ok(())
+    }
+}
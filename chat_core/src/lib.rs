@@ -0,0 +1,13 @@
+mod config;
+mod error;
+mod event;
+mod models;
+mod read;
+mod utils;
+
+pub use config::{load_config, ServerConfig};
+pub use error::ErrorOutput;
+pub use event::AppEvent;
+pub use models::*;
+pub use read::move_read_position;
+pub use utils::{DecodingKey, EncodingKey};
@@ -0,0 +1,259 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct User {
+    pub id: i64,
+    pub ws_id: i64,
+    pub fullname: String,
+    pub email: String,
+    #[sqlx(default)]
+    #[serde(skip)]
+    pub password_hash: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct Workspace {
+    pub id: i64,
+    pub name: String,
+    pub owner_id: i64,
+    // seconds after sending in which messages can be edited, no limit when empty
+    pub edit_window_secs: Option<i64>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct ChatUser {
+    pub id: i64,
+    pub fullname: String,
+    pub email: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, PartialOrd, sqlx::Type)]
+#[sqlx(type_name = "chat_type", rename_all = "snake_case")]
+pub enum ChatType {
+    // the notify triggers send the postgres enum values
+    #[serde(alias = "single")]
+    Single,
+    #[serde(alias = "group")]
+    Group,
+    #[serde(alias = "private_channel")]
+    PrivateChannel,
+    #[serde(alias = "public_channel")]
+    PublicChannel,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "chat_member_role", rename_all = "snake_case")]
+pub enum ChatMemberRole {
+    Owner,
+    Admin,
+    Member,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct Chat {
+    pub id: i64,
+    pub ws_id: i64,
+    pub name: Option<String>,
+    pub r#type: ChatType,
+    pub topic: Option<String>,
+    // chats sent by the notify triggers carry no members
+    #[serde(default)]
+    pub members: Vec<i64>,
+    // older payloads predate pins
+    #[serde(default = "default_max_pins")]
+    pub max_pins: i32,
+    // seconds new messages live for, they never expire when empty
+    pub message_ttl_secs: Option<i64>,
+    // state of the chat for the user listing it
+    #[sqlx(default)]
+    #[serde(default)]
+    pub unread_count: i64,
+    #[sqlx(default, json)]
+    #[serde(default)]
+    pub last_message: Option<Message>,
+    pub created_at: DateTime<Utc>,
+}
+
+// same as the column default
+fn default_max_pins() -> i32 {
+    50
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct Message {
+    pub id: i64,
+    pub chat_id: i64,
+    pub sender_id: i64,
+    // the top level message of the thread this one replies to
+    #[serde(default)]
+    pub parent_id: Option<i64>,
+    // the original message when forwarded, content is the forwarder's comment
+    #[serde(default)]
+    pub forwarded_from: Option<i64>,
+    #[serde(default)]
+    pub quoted_id: Option<i64>,
+    pub content: String,
+    pub images: Vec<String>,
+    #[serde(default)]
+    pub edited_at: Option<DateTime<Utc>>,
+    // retracted messages keep their place with blanked content
+    #[serde(default)]
+    pub deleted_at: Option<DateTime<Utc>>,
+    // key the sender sent the message with, lets the sender match its retries
+    #[serde(default)]
+    pub client_msg_id: Option<String>,
+    // the message is tombstoned after this time
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    // thread summary, only filled in when listing top level messages
+    #[sqlx(default)]
+    #[serde(default)]
+    pub reply_count: i64,
+    #[sqlx(default)]
+    #[serde(default)]
+    pub last_reply_at: Option<DateTime<Utc>>,
+    // reactions grouped by emoji, only filled in when listing messages
+    #[sqlx(default, json)]
+    #[serde(default)]
+    pub reactions: Vec<Reaction>,
+    // the messages `forwarded_from` and `quoted_id` point to, only filled in when
+    // listing messages
+    #[sqlx(default, json)]
+    #[serde(default)]
+    pub forwarded: Option<MessageRef>,
+    #[sqlx(default, json)]
+    #[serde(default)]
+    pub quote: Option<MessageRef>,
+    // options and results when the message is a poll, only filled in when listing
+    // messages
+    #[sqlx(default, json)]
+    #[serde(default)]
+    pub poll: Option<Poll>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Content of a message shown inside another one.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MessageRef {
+    pub id: i64,
+    pub chat_id: i64,
+    pub sender_id: i64,
+    pub content: String,
+    pub images: Vec<String>,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, PartialOrd, sqlx::Type)]
+#[sqlx(type_name = "mention_kind", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum MentionKind {
+    User,
+    Channel,
+    Here,
+}
+
+// the derive leaves out arrays of enums, which are used to store mentions in bulk
+impl sqlx::postgres::PgHasArrayType for MentionKind {
+    fn array_type_info() -> sqlx::postgres::PgTypeInfo {
+        sqlx::postgres::PgTypeInfo::with_name("_mention_kind")
+    }
+}
+
+/// A message which mentioned a user.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct Mention {
+    pub kind: MentionKind,
+    #[sqlx(flatten)]
+    pub message: Message,
+}
+
+/// A pinned message of a chat.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct Pin {
+    pub pinned_by: i64,
+    pub pinned_at: DateTime<Utc>,
+    #[sqlx(flatten)]
+    pub message: Message,
+}
+
+/// A message found by search, with matched terms marked in the html escaped snippet.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct SearchHit {
+    pub snippet: String,
+    #[sqlx(flatten)]
+    pub message: Message,
+}
+
+/// The answer of a slash command, which is only returned to the user who ran it.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CommandReply {
+    pub command: String,
+    pub text: String,
+    // the message the command sent to the chat, if any
+    #[serde(default)]
+    pub message: Option<Message>,
+}
+
+/// A poll on the question in the content of its message.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Poll {
+    pub multi_choice: bool,
+    pub anonymous: bool,
+    // no votes are taken after this time
+    pub closes_at: Option<DateTime<Utc>>,
+    pub voter_count: i64,
+    pub options: Vec<PollOption>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PollOption {
+    pub id: i64,
+    pub text: String,
+    pub votes: i64,
+    // in the order they voted, always empty for anonymous polls
+    pub voters: Vec<i64>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct Reaction {
+    pub emoji: String,
+    // in the order they reacted
+    pub user_ids: Vec<i64>,
+}
+
+/// A message to be sent later. It is pending until it is delivered or fails.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct ScheduledMessage {
+    pub id: i64,
+    pub chat_id: i64,
+    pub sender_id: i64,
+    pub content: String,
+    pub images: Vec<String>,
+    pub parent_id: Option<i64>,
+    pub quoted_id: Option<i64>,
+    pub send_at: DateTime<Utc>,
+    // the message it was delivered as
+    pub message_id: Option<i64>,
+    // why it could not be delivered
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl User {
+    pub fn new(id: i64, fullname: &str, email: &str) -> Self {
+        Self {
+            id,
+            ws_id: 0,
+            fullname: fullname.to_string(),
+            email: email.to_string(),
+            password_hash: None,
+            created_at: Utc::now(),
+        }
+    }
+}
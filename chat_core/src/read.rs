@@ -0,0 +1,27 @@
+use sqlx::PgPool;
+
+/// Move the read position of a chat member forward to a message of the chat, it never
+/// moves back. Returns whether it moved, the database then notifies the reader.
+pub async fn move_read_position(
+    pool: &PgPool,
+    chat_id: i64,
+    user_id: i64,
+    message_id: i64,
+) -> Result<bool, sqlx::Error> {
+    let ret = sqlx::query(
+        r#"
+        UPDATE chat_members
+        SET last_read_message_id = $3
+        WHERE chat_id = $1 AND user_id = $2
+            AND (last_read_message_id IS NULL OR last_read_message_id < $3)
+            AND EXISTS (SELECT 1 FROM messages WHERE id = $3 AND chat_id = $1)
+        "#,
+    )
+    .bind(chat_id)
+    .bind(user_id)
+    .bind(message_id)
+    .execute(pool)
+    .await?;
+
+    Ok(ret.rows_affected() > 0)
+}
@@ -1,5 +1,4 @@
-use crate::{error::AppError, User};
-use anyhow::Result;
+use crate::User;
 use jwt_simple::prelude::*;
 
 const JWT_DURATION: u64 = 60 * 60 * 24 * 7;
@@ -10,24 +9,24 @@ pub struct EncodingKey(Ed25519KeyPair);
 pub struct DecodingKey(Ed25519PublicKey);
 
 impl EncodingKey {
-    pub fn load(pem: &str) -> Result<Self, AppError> {
-        Ok(Self(Ed25519KeyPair::from_pem(pem)?))
+    pub fn load(pem: &str) -> Result<Self, jwt_simple::Error> {
+        Ed25519KeyPair::from_pem(pem).map(Self)
     }
 
-    pub fn sign(&self, user: impl Into<User>) -> Result<String, AppError> {
+    pub fn sign(&self, user: impl Into<User>) -> Result<String, jwt_simple::Error> {
         let claims = Claims::with_custom_claims(user.into(), Duration::from_secs(JWT_DURATION))
             .with_issuer(JWT_ISS)
             .with_audience(JWT_AUD);
-        Ok(self.0.sign(claims)?)
+        self.0.sign(claims)
     }
 }
 
 impl DecodingKey {
-    pub fn load(pem: &str) -> Result<Self, AppError> {
-        Ok(Self(Ed25519PublicKey::from_pem(pem)?))
+    pub fn load(pem: &str) -> Result<Self, jwt_simple::Error> {
+        Ed25519PublicKey::from_pem(pem).map(Self)
     }
 
-    pub fn verify(&self, token: &str) -> Result<User, AppError> {
+    pub fn verify(&self, token: &str) -> Result<User, jwt_simple::Error> {
         let opts = VerificationOptions {
             allowed_issuers: Some(HashSet::from_strings(&[JWT_ISS])),
             allowed_audiences: Some(HashSet::from_strings(&[JWT_AUD])),
@@ -44,8 +43,8 @@ mod tests {
     use super::*;
     use anyhow::{Ok, Result};
 
-    #[tokio::test]
-    async fn jwt_sign_verify_should_work() -> Result<()> {
+    #[test]
+    fn jwt_sign_verify_should_work() -> Result<()> {
         let encoding_pem = include_str!("../../fixtures/encoding.pem");
         let decoding_pem = include_str!("../../fixtures/decoding.pem");
         let ek = EncodingKey::load(encoding_pem)?;
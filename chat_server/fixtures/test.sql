@@ -38,28 +38,34 @@ VALUES (
 UPDATE workspaces SET owner_id=1 WHERE id=1;
 -- insert 4 chats
 -- insert public/private channel
-INSERT INTO chats (ws_id, name, type, members)
-VALUES (
-        1,
-        'general',
-        'public_channel',
-        '{1,2,3,4,5}'
-    ),
-    (
-        1,
-        'private',
-        'private_channel',
-        '{1,2,3}'
-    );
+INSERT INTO chats (ws_id, name, type)
+VALUES (1, 'general', 'public_channel'),
+    (1, 'private', 'private_channel');
 -- insert unnamed chat
-INSERT INTO chats (ws_id, type, members)
-VALUES (
-        1,
-        'single',
-        '{1,2}'
-    ),
-    (
-        1,
-        'group',
-        '{1,3,4}'
-    );
+INSERT INTO chats (ws_id, type)
+VALUES (1, 'single'),
+    (1, 'group');
+-- insert chat members
+INSERT INTO chat_members (chat_id, user_id)
+VALUES (1, 1),
+    (1, 2),
+    (1, 3),
+    (1, 4),
+    (1, 5),
+    (2, 1),
+    (2, 2),
+    (2, 3),
+    (3, 1),
+    (3, 2),
+    (4, 1),
+    (4, 3),
+    (4, 4);
+-- set chat member roles
+UPDATE chat_members
+SET role = 'admin'
+WHERE chat_id = 2
+    AND user_id = 2;
+UPDATE chat_members
+SET role = 'owner'
+WHERE chat_id = 4
+    AND user_id = 3;
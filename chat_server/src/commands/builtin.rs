@@ -0,0 +1,344 @@
+use chrono::{Duration, Utc};
+
+use crate::{
+    error::AppError,
+    models::{AddChatMembers, CreateMessage},
+    policy::ChatAction,
+};
+
+use super::{reply, CommandContext, CommandFuture, SlashCommand};
+
+const MAX_REMINDER_LEN: usize = 1000;
+const MAX_REMINDER_DAYS: i64 = 365;
+
+pub(super) struct Help;
+
+impl SlashCommand for Help {
+    fn name(&self) -> &'static str {
+        "help"
+    }
+
+    fn help(&self) -> &'static str {
+        "- list the commands"
+    }
+
+    fn run<'a>(&'a self, ctx: &'a CommandContext<'a>, _args: &'a str) -> CommandFuture<'a> {
+        Box::pin(async move {
+            let text = ctx
+                .state
+                .commands
+                .iter()
+                .map(|c| format!("/{} {}", c.name(), c.help()))
+                .collect::<Vec<_>>()
+                .join("\n");
+            Ok(reply(self.name(), text))
+        })
+    }
+}
+
+pub(super) struct Topic;
+
+impl SlashCommand for Topic {
+    fn name(&self) -> &'static str {
+        "topic"
+    }
+
+    fn help(&self) -> &'static str {
+        "[text] - set the topic of the chat, or clear it"
+    }
+
+    fn run<'a>(&'a self, ctx: &'a CommandContext<'a>, args: &'a str) -> CommandFuture<'a> {
+        Box::pin(async move {
+            ctx.state
+                .policy()
+                .authorize(ctx.user, ctx.chat.id, ChatAction::Admin)
+                .await?;
+            let chat = ctx.state.set_chat_topic(ctx.chat.id, args).await?;
+            let text = match chat.topic {
+                Some(topic) => format!("Topic set to: {}", topic),
+                None => "Topic cleared.".to_string(),
+            };
+            Ok(reply(self.name(), text))
+        })
+    }
+}
+
+pub(super) struct Invite;
+
+impl SlashCommand for Invite {
+    fn name(&self) -> &'static str {
+        "invite"
+    }
+
+    fn help(&self) -> &'static str {
+        "@user [@user...] - add users to the chat"
+    }
+
+    fn run<'a>(&'a self, ctx: &'a CommandContext<'a>, args: &'a str) -> CommandFuture<'a> {
+        Box::pin(async move {
+            let mut names: Vec<String> = args
+                .split(|c: char| c.is_whitespace() || c == ',')
+                .map(|s| s.trim_start_matches('@').to_lowercase())
+                .filter(|s| !s.is_empty())
+                .collect();
+            names.sort_unstable();
+            names.dedup();
+            if names.is_empty() {
+                return Err(AppError::CommandError(format!(
+                    "Usage: /{} {}",
+                    self.name(),
+                    self.help()
+                )));
+            }
+
+            ctx.state
+                .policy()
+                .authorize(ctx.user, ctx.chat.id, ChatAction::Admin)
+                .await?;
+            let users = ctx
+                .state
+                .find_users_by_names(ctx.user.ws_id, &names)
+                .await?;
+            let unknown: Vec<&str> = names
+                .iter()
+                .filter(|n| {
+                    !users
+                        .iter()
+                        .any(|u| u.email.split('@').next() == Some(n.as_str()))
+                })
+                .map(|n| n.as_str())
+                .collect();
+            if !unknown.is_empty() {
+                return Err(AppError::CommandError(format!(
+                    "Unknown users: @{}",
+                    unknown.join(", @")
+                )));
+            }
+
+            let input = AddChatMembers {
+                members: users.iter().map(|u| u.id).collect(),
+            };
+            ctx.state.add_chat_members(ctx.chat.id, input).await?;
+            let added: Vec<&str> = users.iter().map(|u| u.fullname.as_str()).collect();
+            Ok(reply(self.name(), format!("Added {}.", added.join(", "))))
+        })
+    }
+}
+
+pub(super) struct Leave;
+
+impl SlashCommand for Leave {
+    fn name(&self) -> &'static str {
+        "leave"
+    }
+
+    fn help(&self) -> &'static str {
+        "- leave the chat"
+    }
+
+    fn run<'a>(&'a self, ctx: &'a CommandContext<'a>, _args: &'a str) -> CommandFuture<'a> {
+        Box::pin(async move {
+            let chat = ctx.state.leave_chat(ctx.chat.id, ctx.user.id).await?;
+            let text = match chat.name {
+                Some(name) => format!("You left {}.", name),
+                None => "You left the chat.".to_string(),
+            };
+            Ok(reply(self.name(), text))
+        })
+    }
+}
+
+pub(super) struct Remind;
+
+impl SlashCommand for Remind {
+    fn name(&self) -> &'static str {
+        "remind"
+    }
+
+    fn help(&self) -> &'static str {
+        "[in] <number><s|m|h|d> <text> - remind yourself in this chat, e.g. /remind 30m deploy"
+    }
+
+    fn run<'a>(&'a self, ctx: &'a CommandContext<'a>, args: &'a str) -> CommandFuture<'a> {
+        Box::pin(async move {
+            let usage =
+                || AppError::CommandError(format!("Usage: /{} {}", self.name(), self.help()));
+            let args = args.strip_prefix("in ").unwrap_or(args).trim_start();
+            let (delay, text) = args.split_once(char::is_whitespace).ok_or_else(usage)?;
+            let delay = parse_delay(delay).ok_or_else(usage)?;
+            let text = text.trim();
+            if text.chars().count() > MAX_REMINDER_LEN {
+                return Err(AppError::CommandError(format!(
+                    "Reminder can have at most {} characters.",
+                    MAX_REMINDER_LEN
+                )));
+            }
+
+            let (_, remind_at) = ctx
+                .state
+                .create_reminder(
+                    ctx.user.id,
+                    ctx.chat.id,
+                    text,
+                    Utc::now() + delay,
+                    ctx.client_msg_id,
+                )
+                .await?;
+            Ok(reply(
+                self.name(),
+                format!(
+                    "I will remind you at {}: {}",
+                    remind_at.format("%Y-%m-%d %H:%M UTC"),
+                    text
+                ),
+            ))
+        })
+    }
+}
+
+pub(super) struct Me;
+
+impl SlashCommand for Me {
+    fn name(&self) -> &'static str {
+        "me"
+    }
+
+    fn help(&self) -> &'static str {
+        "<action> - send an action, e.g. /me waves"
+    }
+
+    fn run<'a>(&'a self, ctx: &'a CommandContext<'a>, args: &'a str) -> CommandFuture<'a> {
+        Box::pin(async move {
+            if args.is_empty() {
+                return Err(AppError::CommandError(format!(
+                    "Usage: /{} {}",
+                    self.name(),
+                    self.help()
+                )));
+            }
+
+            let input = CreateMessage {
+                content: format!("_{} {}_", ctx.user.fullname, args),
+                parent_id: ctx.parent_id,
+                client_msg_id: ctx.client_msg_id.map(str::to_string),
+                ..Default::default()
+            };
+            let message = ctx
+                .state
+                .create_message(input, ctx.chat.id, ctx.user.id)
+                .await?;
+            let mut reply = reply(self.name(), "");
+            reply.message = Some(message);
+            Ok(reply)
+        })
+    }
+}
+
+// a positive number with a unit, e.g. `90s`, `15m`, `2h` or `1d`
+fn parse_delay(s: &str) -> Option<Duration> {
+    let unit = s.chars().last()?;
+    let n: i64 = s[..s.len() - unit.len_utf8()].parse().ok()?;
+    if n <= 0 {
+        return None;
+    }
+    let delay = match unit {
+        's' => Duration::try_seconds(n)?,
+        'm' => Duration::try_minutes(n)?,
+        'h' => Duration::try_hours(n)?,
+        'd' => Duration::try_days(n)?,
+        _ => return None,
+    };
+
+    (delay <= Duration::days(MAX_REMINDER_DAYS)).then_some(delay)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{Ok, Result};
+
+    use crate::{models::ListMessages, AppConfig, AppState};
+
+    use super::*;
+
+    #[test]
+    fn parse_delay_should_work() {
+        assert_eq!(parse_delay("90s"), Some(Duration::seconds(90)));
+        assert_eq!(parse_delay("15m"), Some(Duration::minutes(15)));
+        assert_eq!(parse_delay("2h"), Some(Duration::hours(2)));
+        assert_eq!(parse_delay("1d"), Some(Duration::days(1)));
+        assert_eq!(parse_delay("0m"), None);
+        assert_eq!(parse_delay("m"), None);
+        assert_eq!(parse_delay("10w"), None);
+        assert_eq!(parse_delay("366d"), None);
+    }
+
+    #[tokio::test]
+    async fn builtin_commands_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+        // chat 4 has members 1, 3, 4 and is owned by user3
+        let owner = state.find_user_by_email("user3@acme.org").await?.unwrap();
+        let member = state.find_user_by_email("user4@acme.org").await?.unwrap();
+        let chat = state.get_chat_by_id(4).await?;
+
+        // only admins can set the topic
+        let ret = state
+            .run_command(&member, &chat, "/topic release day", None, None)
+            .await;
+        assert!(matches!(ret, Err(AppError::ChatAccessDenied(_))));
+        let reply = state
+            .run_command(&owner, &chat, "/topic  release day ", None, None)
+            .await?
+            .unwrap();
+        assert_eq!(reply.text, "Topic set to: release day");
+        assert_eq!(
+            state.get_chat_by_id(4).await?.topic.as_deref(),
+            Some("release day")
+        );
+
+        // only admins can invite
+        let ret = state
+            .run_command(&member, &chat, "/invite @user2", None, None)
+            .await;
+        assert!(matches!(ret, Err(AppError::ChatAccessDenied(_))));
+        let ret = state
+            .run_command(&owner, &chat, "/invite @user2 @nobody", None, None)
+            .await;
+        assert!(matches!(ret, Err(AppError::CommandError(_))));
+        state
+            .run_command(&owner, &chat, "/invite @User2,", None, None)
+            .await?;
+        assert_eq!(state.get_chat_by_id(4).await?.members, vec![1, 2, 3, 4]);
+
+        let reply = state
+            .run_command(&member, &chat, "/me is on call", None, None)
+            .await?
+            .unwrap();
+        let message = reply.message.unwrap();
+        assert!(message.content.ends_with(" is on call_"));
+        let messages = state.list_messages(ListMessages::default(), 4).await?;
+        assert_eq!(messages.len(), 1);
+
+        let reply = state
+            .run_command(
+                &member,
+                &chat,
+                "/remind in 30m check the deploy",
+                None,
+                None,
+            )
+            .await?
+            .unwrap();
+        assert!(reply.text.ends_with(": check the deploy"));
+        let ret = state
+            .run_command(&member, &chat, "/remind soon check", None, None)
+            .await;
+        assert!(matches!(ret, Err(AppError::CommandError(_))));
+
+        state
+            .run_command(&member, &chat, "/leave", None, None)
+            .await?;
+        assert_eq!(state.get_chat_by_id(4).await?.members, vec![1, 2, 3]);
+
+        Ok(())
+    }
+}
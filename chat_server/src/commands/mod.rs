@@ -0,0 +1,183 @@
+mod builtin;
+
+use std::{collections::BTreeMap, future::Future, pin::Pin};
+
+use chat_core::{Chat, CommandReply, User};
+
+use crate::{error::AppError, models::check_client_msg_id, AppState};
+
+pub(crate) type CommandFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<CommandReply, AppError>> + Send + 'a>>;
+
+/// A command run in place of sending a message which starts with `/<name>`.
+pub(crate) trait SlashCommand: Send + Sync {
+    /// Name after the slash, in lowercase.
+    fn name(&self) -> &'static str;
+
+    /// Arguments and what the command does, shown by `/help`.
+    fn help(&self) -> &'static str;
+
+    /// Run the command for the user in the chat. The user can post to the chat.
+    fn run<'a>(&'a self, ctx: &'a CommandContext<'a>, args: &'a str) -> CommandFuture<'a>;
+}
+
+pub(crate) struct CommandContext<'a> {
+    pub(crate) state: &'a AppState,
+    pub(crate) user: &'a User,
+    pub(crate) chat: &'a Chat,
+    // messages sent by the command go to this thread
+    pub(crate) parent_id: Option<i64>,
+    // what the command creates is created once for retries with the same id
+    pub(crate) client_msg_id: Option<&'a str>,
+}
+
+/// Slash commands by name.
+pub(crate) struct CommandRegistry {
+    commands: BTreeMap<&'static str, Box<dyn SlashCommand>>,
+}
+
+impl CommandRegistry {
+    pub(crate) fn new() -> Self {
+        Self {
+            commands: BTreeMap::new(),
+        }
+    }
+
+    /// A registry with `/help`, `/topic`, `/invite`, `/leave`, `/remind` and `/me`.
+    pub(crate) fn with_builtins() -> Self {
+        let mut registry = Self::new();
+        registry.register(builtin::Help);
+        registry.register(builtin::Topic);
+        registry.register(builtin::Invite);
+        registry.register(builtin::Leave);
+        registry.register(builtin::Remind);
+        registry.register(builtin::Me);
+        registry
+    }
+
+    /// Register a command, replacing one with the same name.
+    pub(crate) fn register(&mut self, command: impl SlashCommand + 'static) {
+        self.commands.insert(command.name(), Box::new(command));
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&dyn SlashCommand> {
+        self.commands.get(name).map(|c| c.as_ref())
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &dyn SlashCommand> {
+        self.commands.values().map(|c| c.as_ref())
+    }
+}
+
+impl AppState {
+    /// Run the slash command in the content of a message, none when the content is
+    /// not a command.
+    pub(crate) async fn run_command(
+        &self,
+        user: &User,
+        chat: &Chat,
+        content: &str,
+        parent_id: Option<i64>,
+        client_msg_id: Option<&str>,
+    ) -> Result<Option<CommandReply>, AppError> {
+        let Some((name, args)) = parse_command(content) else {
+            return Ok(None);
+        };
+        if let Some(key) = client_msg_id {
+            check_client_msg_id(key).map_err(AppError::CommandError)?;
+        }
+        let command = self.commands.get(&name).ok_or_else(|| {
+            AppError::CommandError(format!("Unknown command /{}, try /help.", name))
+        })?;
+
+        let ctx = CommandContext {
+            state: self,
+            user,
+            chat,
+            parent_id,
+            client_msg_id,
+        };
+        command.run(&ctx, args).await.map(Some)
+    }
+}
+
+/// Lowercased name and arguments of a command, e.g. `/Topic  release day` gives
+/// `("topic", "release day")`. Content starting with `//` is a message, see
+/// [`unescape_command`].
+pub(crate) fn parse_command(content: &str) -> Option<(String, &str)> {
+    let rest = content.trim_start().strip_prefix('/')?;
+    if rest.starts_with('/') {
+        return None;
+    }
+
+    let (name, args) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    if name.is_empty() {
+        return None;
+    }
+
+    Some((name.to_lowercase(), args.trim()))
+}
+
+/// Messages starting with `//` are sent with a single slash, so they can start with
+/// something which looks like a command.
+pub(crate) fn unescape_command(content: &mut String) {
+    let start = content.len() - content.trim_start().len();
+    if content[start..].starts_with("//") {
+        content.remove(start);
+    }
+}
+
+pub(crate) fn reply(command: &str, text: impl Into<String>) -> CommandReply {
+    CommandReply {
+        command: command.to_string(),
+        text: text.into(),
+        message: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AppConfig;
+    use anyhow::{Ok, Result};
+
+    #[test]
+    fn parse_command_should_work() {
+        assert_eq!(
+            parse_command("/Topic  release day "),
+            Some(("topic".to_string(), "release day"))
+        );
+        assert_eq!(parse_command(" /leave"), Some(("leave".to_string(), "")));
+        assert_eq!(parse_command("//not a command"), None);
+        assert_eq!(parse_command("/ nothing"), None);
+        assert_eq!(parse_command("hello /me"), None);
+
+        let mut content = "//etc/hosts is empty".to_string();
+        unescape_command(&mut content);
+        assert_eq!(content, "/etc/hosts is empty");
+    }
+
+    #[tokio::test]
+    async fn run_command_should_dispatch_by_name() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+        let user = state.find_user_by_email("user1@acme.org").await?.unwrap();
+        let chat = state.get_chat_by_id(1).await?;
+
+        let ret = state.run_command(&user, &chat, "hello", None, None).await?;
+        assert!(ret.is_none());
+
+        let reply = state
+            .run_command(&user, &chat, "/HELP", None, None)
+            .await?
+            .unwrap();
+        assert_eq!(reply.command, "help");
+        for name in ["topic", "invite", "leave", "remind", "me"] {
+            assert!(reply.text.contains(&format!("/{}", name)));
+        }
+
+        let ret = state.run_command(&user, &chat, "/nope", None, None).await;
+        assert!(matches!(ret, Err(AppError::CommandError(_))));
+
+        Ok(())
+    }
+}
@@ -1,6 +1,5 @@
-use std::fs::File;
-
-use anyhow::{bail, Result};
+use anyhow::Result;
+use chat_core::{load_config, ServerConfig};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -15,26 +14,8 @@ pub struct AuthConfig {
     pub pk: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ServerConfig {
-    pub port: u16,
-    pub db_url: String,
-}
-
 impl AppConfig {
     pub fn load() -> Result<Self> {
-        // read from ./app.yml, or /etc/config/app.yml, or from env CHAT_CONFIG
-        let ret = match (
-            File::open("app.yml"),
-            File::open("/etc/config/app.yml"),
-            std::env::var("CHAT_CONFIG"),
-        ) {
-            (Ok(file), _, _) => serde_yaml::from_reader(file),
-            (_, Ok(file), _) => serde_yaml::from_reader(file),
-            (_, _, Ok(path)) => serde_yaml::from_reader(File::open(path)?),
-            _ => bail!("No config file found"),
-        };
-
-        Ok(ret?)
+        load_config("app.yml", "CHAT_CONFIG")
     }
 }
@@ -1,17 +1,15 @@
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use axum::response::Json;
-use serde::Deserialize;
-use serde::Serialize;
 use thiserror::Error;
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ErrorOutput {
-    error: String,
-}
+pub use chat_core::ErrorOutput;
 
 #[derive(Error, Debug)]
 pub enum AppError {
+    #[error("not found: {0}")]
+    NotFound(String),
+
     #[error("email: {0} already exists")]
     EmailAlreadyExists(String),
 
@@ -24,6 +22,48 @@ pub enum AppError {
     #[error("delete chat error: {0}")]
     DeleteChatError(String),
 
+    #[error("chat member error: {0}")]
+    ChatMemberError(String),
+
+    #[error("chat access denied: {0}")]
+    ChatAccessDenied(String),
+
+    #[error("create message error: {0}")]
+    CreateMessageError(String),
+
+    #[error("update message error: {0}")]
+    UpdateMessageError(String),
+
+    #[error("message access denied: {0}")]
+    MessageAccessDenied(String),
+
+    #[error("command error: {0}")]
+    CommandError(String),
+
+    #[error("schedule message error: {0}")]
+    ScheduleMessageError(String),
+
+    #[error("poll error: {0}")]
+    PollError(String),
+
+    #[error("pin error: {0}")]
+    PinError(String),
+
+    #[error("reaction error: {0}")]
+    ReactionError(String),
+
+    #[error("list messages error: {0}")]
+    ListMessagesError(String),
+
+    #[error("search error: {0}")]
+    SearchError(String),
+
+    #[error("update workspace error: {0}")]
+    UpdateWorkspaceError(String),
+
+    #[error("workspace access denied: {0}")]
+    WorkspaceAccessDenied(String),
+
     #[error("sql error: {0}")]
     SqlxError(#[from] sqlx::Error),
 
@@ -37,17 +77,10 @@ pub enum AppError {
     HttpHeaderError(#[from] axum::http::header::InvalidHeaderValue),
 }
 
-impl ErrorOutput {
-    pub fn new(error: impl Into<String>) -> Self {
-        Self {
-            error: error.into(),
-        }
-    }
-}
-
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
         let status = match &self {
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
             AppError::EmailAlreadyExists(_) => StatusCode::CONFLICT,
             AppError::SqlxError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::PasswordHashError(_) => StatusCode::UNPROCESSABLE_ENTITY,
@@ -56,6 +89,20 @@ impl IntoResponse for AppError {
             AppError::CreateChatError(_) => StatusCode::BAD_REQUEST,
             AppError::UpdateChatError(_) => StatusCode::BAD_REQUEST,
             AppError::DeleteChatError(_) => StatusCode::BAD_REQUEST,
+            AppError::ChatMemberError(_) => StatusCode::BAD_REQUEST,
+            AppError::ChatAccessDenied(_) => StatusCode::FORBIDDEN,
+            AppError::CreateMessageError(_) => StatusCode::BAD_REQUEST,
+            AppError::UpdateMessageError(_) => StatusCode::BAD_REQUEST,
+            AppError::MessageAccessDenied(_) => StatusCode::FORBIDDEN,
+            AppError::CommandError(_) => StatusCode::BAD_REQUEST,
+            AppError::ScheduleMessageError(_) => StatusCode::BAD_REQUEST,
+            AppError::PollError(_) => StatusCode::BAD_REQUEST,
+            AppError::PinError(_) => StatusCode::BAD_REQUEST,
+            AppError::ReactionError(_) => StatusCode::BAD_REQUEST,
+            AppError::ListMessagesError(_) => StatusCode::BAD_REQUEST,
+            AppError::SearchError(_) => StatusCode::BAD_REQUEST,
+            AppError::UpdateWorkspaceError(_) => StatusCode::BAD_REQUEST,
+            AppError::WorkspaceAccessDenied(_) => StatusCode::FORBIDDEN,
         };
 
         (status, Json(ErrorOutput::new(self.to_string()))).into_response()
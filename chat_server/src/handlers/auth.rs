@@ -1,7 +1,7 @@
 use crate::{
     error::{AppError, ErrorOutput},
     models::{CreateUser, SigninUser},
-    AppState, User,
+    AppState,
 };
 use anyhow::Result;
 use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
@@ -16,14 +16,11 @@ pub(crate) async fn signup_handler(
     State(state): State<AppState>,
     Json(input): Json<CreateUser>,
 ) -> Result<impl IntoResponse, AppError> {
-    if User::find_by_email(&input.email, &state.pool)
-        .await?
-        .is_some()
-    {
+    if state.find_user_by_email(&input.email).await?.is_some() {
         return Err(AppError::EmailAlreadyExists(input.email));
     }
 
-    let user = User::create(&input, &state.pool).await?;
+    let user = state.create_user(&input).await?;
     let token = state.ek.sign(user)?;
     Ok((StatusCode::CREATED, Json(AuthOutput { token })).into_response())
 }
@@ -32,7 +29,7 @@ pub(crate) async fn signin_handler(
     State(state): State<AppState>,
     Json(input): Json<SigninUser>,
 ) -> Result<impl IntoResponse, AppError> {
-    match User::verify(&input, &state.pool).await? {
+    match state.verify_user(&input).await? {
         Some(user) => {
             let token = state.ek.sign(user)?;
             Ok((StatusCode::OK, Json(AuthOutput { token })).into_response())
@@ -1,7 +1,8 @@
 use crate::{
     error::AppError,
-    models::{Chat, CreateChat, UpdateChat, Workspace},
-    AppState, User,
+    models::{AddChatMembers, CreateChat, PinMessage, ReadChat, UpdateChat},
+    policy::{ChatAction, Policy},
+    AppState,
 };
 use axum::{
     extract::{Path, State},
@@ -9,12 +10,19 @@ use axum::{
     response::IntoResponse,
     Extension, Json,
 };
+use chat_core::User;
 
 pub(crate) async fn list_chats_handler(
     Extension(user): Extension<User>,
     State(state): State<AppState>,
 ) -> Result<impl IntoResponse, AppError> {
-    let chats = Chat::fetch_all(user.ws_id as _, &state.pool).await?;
+    let policy = state.policy();
+    let mut chats = Vec::new();
+    for chat in state.fetch_chats(user.ws_id as _, user.id).await? {
+        if policy.can_read_chat(&user, &chat).await? {
+            chats.push(chat);
+        }
+    }
     Ok((StatusCode::OK, Json(chats)))
 }
 
@@ -23,15 +31,19 @@ pub(crate) async fn create_chat_handler(
     State(state): State<AppState>,
     Json(input): Json<CreateChat>,
 ) -> Result<impl IntoResponse, AppError> {
-    let chat = Chat::create(input, user.ws_id as _, &state.pool).await?;
+    let chat = state.create_chat(input, user.ws_id as _, user.id).await?;
     Ok((StatusCode::OK, Json(chat)))
 }
 
 pub(crate) async fn get_chat_handler(
+    Extension(user): Extension<User>,
     State(state): State<AppState>,
     Path(id): Path<i64>,
 ) -> Result<impl IntoResponse, AppError> {
-    let chat = Chat::get_by_id(id, &state.pool).await?;
+    let chat = state
+        .policy()
+        .authorize(&user, id, ChatAction::Read)
+        .await?;
     Ok((StatusCode::OK, Json(chat)))
 }
 
@@ -41,14 +53,11 @@ pub(crate) async fn update_chat_handler(
     Path(id): Path<i64>,
     Json(input): Json<UpdateChat>,
 ) -> Result<impl IntoResponse, AppError> {
-    let chat = Chat::get_by_id(id, &state.pool).await?;
-    if chat.ws_id != user.ws_id {
-        return Err(AppError::UpdateChatError(
-            "Can not update the chat which in other workspace.".to_string(),
-        ));
-    }
-
-    let chat = Chat::update(id, input, &state.pool).await?;
+    state
+        .policy()
+        .authorize(&user, id, ChatAction::Admin)
+        .await?;
+    let chat = state.update_chat(id, input).await?;
     Ok((StatusCode::OK, Json(chat)))
 }
 
@@ -57,31 +66,127 @@ pub(crate) async fn delete_chat_handler(
     State(state): State<AppState>,
     Path(id): Path<i64>,
 ) -> Result<impl IntoResponse, AppError> {
-    let chat = Chat::get_by_id(id, &state.pool).await?;
-    match Workspace::find_by_id(chat.ws_id, &state.pool).await? {
-        Some(ws) => {
-            if ws.owner_id != user.id {
-                return Err(AppError::DeleteChatError(
-                    "Only workspace owner can delete the chat.".to_string(),
-                ));
-            }
-        }
-        _ => {
-            return Err(AppError::DeleteChatError(
-                "Workspace of chat not exist.".to_string(),
-            ));
-        }
-    }
+    state
+        .policy()
+        .authorize(&user, id, ChatAction::Delete)
+        .await?;
+    state.delete_chat(id).await?;
+    Ok((StatusCode::OK, Json("success".to_string())))
+}
+
+pub(crate) async fn add_chat_members_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(input): Json<AddChatMembers>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .policy()
+        .authorize(&user, id, ChatAction::Admin)
+        .await?;
+    let chat = state.add_chat_members(id, input).await?;
+    Ok((StatusCode::OK, Json(chat)))
+}
+
+pub(crate) async fn remove_chat_member_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, member_id)): Path<(i64, i64)>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .policy()
+        .authorize(&user, id, ChatAction::Admin)
+        .await?;
+    let chat = state.remove_chat_member(id, member_id).await?;
+    Ok((StatusCode::OK, Json(chat)))
+}
 
-    Chat::delete(id, &state.pool).await?;
+pub(crate) async fn read_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(input): Json<ReadChat>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .policy()
+        .authorize(&user, id, ChatAction::Write)
+        .await?;
+    let chat = state.read_chat(id, user.id, input).await?;
+    Ok((StatusCode::OK, Json(chat)))
+}
+
+pub(crate) async fn join_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .policy()
+        .authorize(&user, id, ChatAction::Read)
+        .await?;
+    let chat = state.join_chat(id, user.id).await?;
+    Ok((StatusCode::OK, Json(chat)))
+}
+
+pub(crate) async fn leave_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .policy()
+        .authorize(&user, id, ChatAction::Read)
+        .await?;
+    let chat = state.leave_chat(id, user.id).await?;
+    Ok((StatusCode::OK, Json(chat)))
+}
+
+pub(crate) async fn list_pins_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .policy()
+        .authorize(&user, id, ChatAction::Read)
+        .await?;
+    let pins = state.list_pins(id).await?;
+    Ok((StatusCode::OK, Json(pins)))
+}
+
+pub(crate) async fn pin_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(input): Json<PinMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .policy()
+        .authorize(&user, id, ChatAction::Write)
+        .await?;
+    let pin = state.pin_message(id, user.id, input).await?;
+    Ok((StatusCode::OK, Json(pin)))
+}
+
+pub(crate) async fn unpin_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, msg_id)): Path<(i64, i64)>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .policy()
+        .authorize(&user, id, ChatAction::Write)
+        .await?;
+    state.unpin_message(id, msg_id).await?;
     Ok((StatusCode::OK, Json("success".to_string())))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{models::ChatType, AppConfig};
+    use crate::{models::CreateMessage, AppConfig};
     use anyhow::{Ok, Result};
+    use chat_core::{Chat, ChatType, Pin};
     use http_body_util::BodyExt;
 
     #[tokio::test]
@@ -89,8 +194,9 @@ mod tests {
         let config = AppConfig::load()?;
         let (_tdb, state) = AppState::new_for_test(config).await?;
 
+        let user = state.find_user_by_email("user1@acme.org").await?.unwrap();
         let id: i64 = 1;
-        let ret = get_chat_handler(State(state), Path(id))
+        let ret = get_chat_handler(Extension(user), State(state), Path(id))
             .await
             .into_response();
         assert_eq!(ret.status(), StatusCode::OK);
@@ -103,14 +209,77 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn chat_update_chat_handler_should_work() -> Result<()> {
+    async fn chat_get_chat_handler_should_not_work() -> Result<()> {
         let config = AppConfig::load()?;
         let (_tdb, state) = AppState::new_for_test(config).await?;
 
-        let user = User::find_by_email("user1@acme.org", &state.pool)
+        // user4 is not a member of the private channel
+        let user = state.find_user_by_email("user4@acme.org").await?.unwrap();
+        let ret = get_chat_handler(Extension(user), State(state.clone()), Path(2))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+
+        let user = User::new(10, "test_user", "test_user@acme.org");
+        let ret = get_chat_handler(Extension(user), State(state), Path(2))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_list_chats_handler_should_work() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+
+        let user = state.find_user_by_email("user4@acme.org").await?.unwrap();
+        let ret = list_chats_handler(Extension(user), State(state))
             .await?
-            .unwrap();
-        let input = UpdateChat::new("pub", &[1, 2, 3], true);
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let chats = serde_json::from_slice::<Vec<Chat>>(&body)?;
+        let ids: Vec<_> = chats.iter().map(|c| c.id).collect();
+        assert_eq!(ids, vec![1, 4]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_read_chat_handler_should_work() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+
+        let input = CreateMessage::new("hello", &[]);
+        let msg = state.create_message(input, 4, 3).await?;
+
+        let user = state.find_user_by_email("user4@acme.org").await?.unwrap();
+        let chats = state.fetch_chats(1, user.id).await?;
+        assert_eq!(chats[3].unread_count, 1);
+
+        let input = ReadChat::new(Some(msg.id));
+        let ret = read_chat_handler(Extension(user), State(state), Path(4), Json(input))
+            .await?
+            .into_response();
+
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let chat = serde_json::from_slice::<Chat>(&body)?;
+        assert_eq!(chat.unread_count, 0);
+        assert_eq!(chat.last_message.unwrap().id, msg.id);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_update_chat_handler_should_work() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+
+        let user = state.find_user_by_email("user1@acme.org").await?.unwrap();
+        let input = UpdateChat::new("pub", Some(true));
         let ret = update_chat_handler(Extension(user), State(state), Path(1), Json(input))
             .await?
             .into_response();
@@ -120,7 +289,7 @@ mod tests {
         let chat = serde_json::from_slice::<Chat>(&body)?;
         assert_eq!(chat.name.unwrap(), "pub");
         assert_eq!(chat.r#type, ChatType::PublicChannel);
-        assert_eq!(chat.members.len(), 3);
+        assert_eq!(chat.members.len(), 5);
 
         Ok(())
     }
@@ -131,7 +300,7 @@ mod tests {
         let (_tdb, state) = AppState::new_for_test(config).await?;
 
         let user = User::new(10, "test_user", "test_user@acme.org");
-        let input = UpdateChat::new("pub", &[1, 2, 3], true);
+        let input = UpdateChat::new("pub", Some(true));
         let ret = update_chat_handler(Extension(user), State(state), Path(1), Json(input)).await;
         assert!(ret.is_err());
 
@@ -144,9 +313,122 @@ mod tests {
         let (_tdb, state) = AppState::new_for_test(config).await?;
 
         let user = User::new(10, "test_user", "test_user@acme.org");
-        let ret = delete_chat_handler(Extension(user), State(state), Path(1)).await;
+        let ret = delete_chat_handler(Extension(user), State(state.clone()), Path(1)).await;
         assert!(ret.is_err());
 
+        let user = state.find_user_by_email("user2@acme.org").await?.unwrap();
+        let ret = delete_chat_handler(Extension(user), State(state.clone()), Path(1))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+
+        // the owner of chat 4 is not the workspace owner
+        let user = state.find_user_by_email("user3@acme.org").await?.unwrap();
+        let ret = delete_chat_handler(Extension(user), State(state), Path(4))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_member_handlers_should_work() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+
+        let user = state.find_user_by_email("user1@acme.org").await?.unwrap();
+        let input = AddChatMembers::new(&[5]);
+        let ret = add_chat_members_handler(
+            Extension(user.clone()),
+            State(state.clone()),
+            Path(2),
+            Json(input),
+        )
+        .await?
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let chat = serde_json::from_slice::<Chat>(&body)?;
+        assert_eq!(chat.members, vec![1, 2, 3, 5]);
+        assert_eq!(chat.r#type, ChatType::PrivateChannel);
+
+        let ret = remove_chat_member_handler(Extension(user), State(state.clone()), Path((2, 5)))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+
+        let user = state.find_user_by_email("user5@acme.org").await?.unwrap();
+        let ret = remove_chat_member_handler(Extension(user), State(state), Path((2, 3)))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_join_leave_handlers_should_work() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+
+        let user = state.find_user_by_email("user5@acme.org").await?.unwrap();
+        let ret = leave_chat_handler(Extension(user.clone()), State(state.clone()), Path(1))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+
+        let ret = join_chat_handler(Extension(user.clone()), State(state.clone()), Path(1))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let chat = serde_json::from_slice::<Chat>(&body)?;
+        assert!(chat.members.contains(&user.id));
+
+        let ret = join_chat_handler(Extension(user), State(state), Path(2))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_pin_handlers_should_work() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+
+        let input = CreateMessage::new("onboarding links", &[]);
+        let msg = state.create_message(input, 1, 1).await?;
+
+        let user = state.find_user_by_email("user2@acme.org").await?.unwrap();
+        let input = PinMessage::new(msg.id);
+        let ret = pin_message_handler(
+            Extension(user.clone()),
+            State(state.clone()),
+            Path(1),
+            Json(input),
+        )
+        .await?
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+
+        let ret = list_pins_handler(Extension(user.clone()), State(state.clone()), Path(1))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let pins = serde_json::from_slice::<Vec<Pin>>(&body)?;
+        assert_eq!(pins.len(), 1);
+        assert_eq!(pins[0].pinned_by, user.id);
+
+        let ret = unpin_message_handler(Extension(user), State(state.clone()), Path((1, msg.id)))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        assert!(state.list_pins(1).await?.is_empty());
+
         Ok(())
     }
 }
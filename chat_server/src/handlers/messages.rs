@@ -1,9 +1,714 @@
-use axum::response::IntoResponse;
+use crate::{
+    commands::unescape_command,
+    error::AppError,
+    models::{
+        AddReaction, CreateMessage, ForwardMessage, ListMentions, ListMessages, ScheduleMessage,
+        SearchMessages, UpdateMessage, UpdateScheduledMessage, VotePoll,
+    },
+    policy::{ChatAction, Policy},
+    AppState,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Extension, Json,
+};
+use chat_core::User;
 
-pub(crate) async fn send_message_handler() -> impl IntoResponse {
-    "send_message"
+const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Send a message. A retry with the same `Idempotency-Key` header or `client_msg_id`
+/// returns the message sent first. A message starting with `/` runs a slash command
+/// instead, whose reply is only returned to the sender.
+pub(crate) async fn send_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+    Json(mut input): Json<CreateMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = state
+        .policy()
+        .authorize(&user, id, ChatAction::Write)
+        .await?;
+    if input.client_msg_id.is_none() {
+        if let Some(key) = headers.get(IDEMPOTENCY_KEY_HEADER) {
+            let key = key.to_str().map_err(|_| {
+                AppError::CreateMessageError("Invalid idempotency key.".to_string())
+            })?;
+            input.client_msg_id = Some(key.to_string());
+        }
+    }
+    // with the same key /me and /remind create their message or reminder once and
+    // /invite can be repeated, but a repeated /leave is refused as the sender left
+    if let Some(reply) = state
+        .run_command(
+            &user,
+            &chat,
+            &input.content,
+            input.parent_id,
+            input.client_msg_id.as_deref(),
+        )
+        .await?
+    {
+        return Ok((StatusCode::OK, Json(reply)).into_response());
+    }
+    unescape_command(&mut input.content);
+    let message = state.create_message(input, id, user.id).await?;
+    Ok((StatusCode::CREATED, Json(message)).into_response())
+}
+
+/// Send a message to the chat later.
+pub(crate) async fn schedule_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(input): Json<ScheduleMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .policy()
+        .authorize(&user, id, ChatAction::Write)
+        .await?;
+    let scheduled = state.schedule_message(input, id, user.id).await?;
+    Ok((StatusCode::CREATED, Json(scheduled)))
+}
+
+pub(crate) async fn list_scheduled_messages_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let scheduled = state.list_scheduled_messages(user.id).await?;
+    Ok(Json(scheduled))
+}
+
+pub(crate) async fn update_scheduled_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(input): Json<UpdateScheduledMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    let scheduled = state.update_scheduled_message(input, id, user.id).await?;
+    Ok(Json(scheduled))
+}
+
+pub(crate) async fn cancel_scheduled_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.cancel_scheduled_message(id, user.id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Forward a message of a chat the user can read into a chat the user can post to.
+pub(crate) async fn forward_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, msg_id)): Path<(i64, i64)>,
+    Json(input): Json<ForwardMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    let policy = state.policy();
+    policy.authorize(&user, id, ChatAction::Read).await?;
+    policy
+        .authorize(&user, input.chat_id, ChatAction::Write)
+        .await?;
+    let message = state.forward_message(input, id, msg_id, user.id).await?;
+    Ok((StatusCode::CREATED, Json(message)))
+}
+
+pub(crate) async fn update_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, msg_id)): Path<(i64, i64)>,
+    Json(input): Json<UpdateMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .policy()
+        .authorize(&user, id, ChatAction::Write)
+        .await?;
+    let message = state.update_message(input, id, msg_id, &user).await?;
+    Ok((StatusCode::OK, Json(message)))
+}
+
+pub(crate) async fn delete_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, msg_id)): Path<(i64, i64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let policy = state.policy();
+    let chat = policy.authorize(&user, id, ChatAction::Read).await?;
+    // besides the sender, chat admins and the workspace owner can retract messages
+    let can_admin = policy.can_admin_chat(&user, &chat).await?;
+    let message = state.delete_message(id, msg_id, &user, can_admin).await?;
+    Ok((StatusCode::OK, Json(message)))
+}
+
+pub(crate) async fn add_reaction_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, msg_id)): Path<(i64, i64)>,
+    Json(input): Json<AddReaction>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .policy()
+        .authorize(&user, id, ChatAction::Write)
+        .await?;
+    let reactions = state.add_reaction(id, msg_id, user.id, input).await?;
+    Ok((StatusCode::OK, Json(reactions)))
+}
+
+pub(crate) async fn remove_reaction_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, msg_id, emoji)): Path<(i64, i64, String)>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .policy()
+        .authorize(&user, id, ChatAction::Write)
+        .await?;
+    let reactions = state.remove_reaction(id, msg_id, user.id, &emoji).await?;
+    Ok((StatusCode::OK, Json(reactions)))
+}
+
+/// Vote on a poll, replacing the user's earlier votes.
+pub(crate) async fn vote_poll_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, msg_id)): Path<(i64, i64)>,
+    Json(input): Json<VotePoll>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .policy()
+        .authorize(&user, id, ChatAction::Write)
+        .await?;
+    let poll = state.vote_poll(id, msg_id, user.id, input).await?;
+    Ok(Json(poll))
+}
+
+pub(crate) async fn list_replies_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, msg_id)): Path<(i64, i64)>,
+    Query(input): Query<ListMessages>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .policy()
+        .authorize(&user, id, ChatAction::Read)
+        .await?;
+    let messages = state.list_replies(input, id, msg_id).await?;
+    Ok((StatusCode::OK, Json(messages)))
 }
 
-pub(crate) async fn list_message_handler() -> impl IntoResponse {
-    "list_message"
+pub(crate) async fn list_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Query(input): Query<ListMessages>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .policy()
+        .authorize(&user, id, ChatAction::Read)
+        .await?;
+    let messages = state.list_messages(input, id).await?;
+    Ok((StatusCode::OK, Json(messages)))
+}
+
+pub(crate) async fn list_mentions_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Query(input): Query<ListMentions>,
+) -> Result<impl IntoResponse, AppError> {
+    let mentions = state.list_mentions(input, user.id).await?;
+    Ok((StatusCode::OK, Json(mentions)))
+}
+
+pub(crate) async fn search_messages_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Query(input): Query<SearchMessages>,
+) -> Result<impl IntoResponse, AppError> {
+    let hits = state.search_messages(input, user.ws_id, user.id).await?;
+    Ok((StatusCode::OK, Json(hits)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AppConfig;
+    use anyhow::{Ok, Result};
+    use chat_core::{CommandReply, Mention, Message, Reaction, SearchHit};
+    use http_body_util::BodyExt;
+
+    #[tokio::test]
+    async fn send_message_handler_should_work() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+
+        let user = state.find_user_by_email("user1@acme.org").await?.unwrap();
+        let input = CreateMessage::new("hello world", &[]);
+        let ret = send_message_handler(
+            Extension(user),
+            State(state),
+            Path(1),
+            HeaderMap::new(),
+            Json(input),
+        )
+        .await?
+        .into_response();
+
+        assert_eq!(ret.status(), StatusCode::CREATED);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let msg = serde_json::from_slice::<Message>(&body)?;
+        assert_eq!(msg.chat_id, 1);
+        assert_eq!(msg.content, "hello world");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn send_message_handler_should_be_idempotent() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let user = state.find_user_by_email("user1@acme.org").await?.unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(IDEMPOTENCY_KEY_HEADER, "a6f0c2".parse()?);
+        let mut ids = vec![];
+        for _ in 0..2 {
+            let input = CreateMessage::new("only once", &[]);
+            let ret = send_message_handler(
+                Extension(user.clone()),
+                State(state.clone()),
+                Path(1),
+                headers.clone(),
+                Json(input),
+            )
+            .await?
+            .into_response();
+            assert_eq!(ret.status(), StatusCode::CREATED);
+            let body = ret.into_body().collect().await?.to_bytes();
+            let msg = serde_json::from_slice::<Message>(&body)?;
+            assert_eq!(msg.client_msg_id.as_deref(), Some("a6f0c2"));
+            ids.push(msg.id);
+        }
+        assert_eq!(ids[0], ids[1]);
+        let messages = state.list_messages(ListMessages::default(), 1).await?;
+        assert_eq!(messages.len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn send_message_handler_should_run_commands() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let user = state.find_user_by_email("user1@acme.org").await?.unwrap();
+
+        let input = CreateMessage::new("/topic release day", &[]);
+        let ret = send_message_handler(
+            Extension(user.clone()),
+            State(state.clone()),
+            Path(1),
+            HeaderMap::new(),
+            Json(input),
+        )
+        .await?
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let reply = serde_json::from_slice::<CommandReply>(&body)?;
+        assert_eq!(reply.command, "topic");
+        // the command is not stored as a message
+        let messages = state.list_messages(ListMessages::default(), 1).await?;
+        assert!(messages.is_empty());
+
+        // a retried command with the same key sends its message once
+        let mut headers = HeaderMap::new();
+        headers.insert(IDEMPOTENCY_KEY_HEADER, "b71d09".parse()?);
+        for _ in 0..2 {
+            let input = CreateMessage::new("/me waves", &[]);
+            let ret = send_message_handler(
+                Extension(user.clone()),
+                State(state.clone()),
+                Path(1),
+                headers.clone(),
+                Json(input),
+            )
+            .await?
+            .into_response();
+            assert_eq!(ret.status(), StatusCode::OK);
+        }
+        let messages = state.list_messages(ListMessages::default(), 1).await?;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].client_msg_id.as_deref(), Some("b71d09"));
+
+        let input = CreateMessage::new("//topic is a command", &[]);
+        let ret = send_message_handler(
+            Extension(user),
+            State(state),
+            Path(1),
+            HeaderMap::new(),
+            Json(input),
+        )
+        .await?
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::CREATED);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let msg = serde_json::from_slice::<Message>(&body)?;
+        assert_eq!(msg.content, "/topic is a command");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn retried_membership_commands_should_work() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let user = state.find_user_by_email("user1@acme.org").await?.unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(IDEMPOTENCY_KEY_HEADER, "9e3f2a".parse()?);
+
+        // chat 2 has members 1, 2, 3
+        for _ in 0..2 {
+            let input = CreateMessage::new("/invite @user4", &[]);
+            let ret = send_message_handler(
+                Extension(user.clone()),
+                State(state.clone()),
+                Path(2),
+                headers.clone(),
+                Json(input),
+            )
+            .await?
+            .into_response();
+            assert_eq!(ret.status(), StatusCode::OK);
+        }
+        assert_eq!(state.get_chat_by_id(2).await?.members, vec![1, 2, 3, 4]);
+
+        let input = CreateMessage::new("/leave", &[]);
+        let ret = send_message_handler(
+            Extension(user.clone()),
+            State(state.clone()),
+            Path(2),
+            headers.clone(),
+            Json(input),
+        )
+        .await?
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let input = CreateMessage::new("/leave", &[]);
+        let ret = send_message_handler(
+            Extension(user),
+            State(state.clone()),
+            Path(2),
+            headers,
+            Json(input),
+        )
+        .await
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+        assert_eq!(state.get_chat_by_id(2).await?.members, vec![2, 3, 4]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn send_message_handler_should_not_work() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+
+        let user = state.find_user_by_email("user4@acme.org").await?.unwrap();
+        let input = CreateMessage::new("hello world", &[]);
+        let ret = send_message_handler(
+            Extension(user),
+            State(state),
+            Path(3),
+            HeaderMap::new(),
+            Json(input),
+        )
+        .await
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_message_handler_should_work() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+
+        let user = state.find_user_by_email("user1@acme.org").await?.unwrap();
+        let input = CreateMessage::new("helo", &[]);
+        let msg = state.create_message(input, 1, user.id).await?;
+
+        let input = UpdateMessage::new(Some("hello"), None);
+        let ret = update_message_handler(
+            Extension(user),
+            State(state.clone()),
+            Path((1, msg.id)),
+            Json(input),
+        )
+        .await?
+        .into_response();
+
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let msg = serde_json::from_slice::<Message>(&body)?;
+        assert_eq!(msg.content, "hello");
+        assert!(msg.edited_at.is_some());
+
+        // only the sender can edit
+        let user = state.find_user_by_email("user2@acme.org").await?.unwrap();
+        let input = UpdateMessage::new(Some("hi"), None);
+        let ret = update_message_handler(
+            Extension(user),
+            State(state),
+            Path((1, msg.id)),
+            Json(input),
+        )
+        .await
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_message_handler_should_work() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+
+        // user3 owns chat 4, user4 is a plain member
+        let user3 = state.find_user_by_email("user3@acme.org").await?.unwrap();
+        let user4 = state.find_user_by_email("user4@acme.org").await?.unwrap();
+        let input = CreateMessage::new("hello", &[]);
+        let msg = state.create_message(input, 4, user4.id).await?;
+        let input = CreateMessage::new("hello", &[]);
+        let msg3 = state.create_message(input, 4, user3.id).await?;
+
+        let ret =
+            delete_message_handler(Extension(user4), State(state.clone()), Path((4, msg3.id)))
+                .await
+                .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+
+        let ret = delete_message_handler(Extension(user3), State(state), Path((4, msg.id)))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let msg = serde_json::from_slice::<Message>(&body)?;
+        assert_eq!(msg.content, "");
+        assert!(msg.deleted_at.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_message_handler_should_work() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+
+        let user = state.find_user_by_email("user1@acme.org").await?.unwrap();
+        for i in 0..3 {
+            let input = CreateMessage::new(&format!("message {}", i), &[]);
+            state.create_message(input, 1, user.id).await?;
+        }
+
+        let input = ListMessages::new(None, None, Some(2));
+        let ret = list_message_handler(Extension(user), State(state), Path(1), Query(input))
+            .await?
+            .into_response();
+
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let msgs = serde_json::from_slice::<Vec<Message>>(&body)?;
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(msgs[0].content, "message 2");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_replies_handler_should_work() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+
+        let user = state.find_user_by_email("user1@acme.org").await?.unwrap();
+        let parent = state
+            .create_message(CreateMessage::new("topic", &[]), 1, user.id)
+            .await?;
+        let input = CreateMessage::reply(parent.id, "reply");
+        state.create_message(input, 1, user.id).await?;
+
+        let input = ListMessages::default();
+        let ret = list_replies_handler(
+            Extension(user),
+            State(state),
+            Path((1, parent.id)),
+            Query(input),
+        )
+        .await?
+        .into_response();
+
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let msgs = serde_json::from_slice::<Vec<Message>>(&body)?;
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(msgs[0].parent_id, Some(parent.id));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reaction_handlers_should_work() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+
+        let user = state.find_user_by_email("user1@acme.org").await?.unwrap();
+        let msg = state
+            .create_message(CreateMessage::new("hello", &[]), 3, user.id)
+            .await?;
+
+        let input = AddReaction::new("👍");
+        let ret = add_reaction_handler(
+            Extension(user.clone()),
+            State(state.clone()),
+            Path((3, msg.id)),
+            Json(input),
+        )
+        .await?
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let reactions = serde_json::from_slice::<Vec<Reaction>>(&body)?;
+        assert_eq!(reactions[0].user_ids, vec![user.id]);
+
+        let path = Path((3, msg.id, "👍".to_string()));
+        let ret = remove_reaction_handler(Extension(user), State(state.clone()), path)
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+
+        // only members can react
+        let user = state.find_user_by_email("user4@acme.org").await?.unwrap();
+        let input = AddReaction::new("👍");
+        let ret = add_reaction_handler(
+            Extension(user),
+            State(state),
+            Path((3, msg.id)),
+            Json(input),
+        )
+        .await
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_mentions_handler_should_work() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+
+        let input = CreateMessage::new("hi @user2", &[]);
+        let msg = state.create_message(input, 1, 1).await?;
+
+        let user = state.find_user_by_email("user2@acme.org").await?.unwrap();
+        let input = ListMentions::default();
+        let ret = list_mentions_handler(Extension(user), State(state), Query(input))
+            .await?
+            .into_response();
+
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let mentions = serde_json::from_slice::<Vec<Mention>>(&body)?;
+        assert_eq!(mentions.len(), 1);
+        assert_eq!(mentions[0].message.id, msg.id);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn search_messages_handler_should_work() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+
+        let input = CreateMessage::new("hello search", &[]);
+        let msg = state.create_message(input, 1, 1).await?;
+
+        let user = state.find_user_by_email("user2@acme.org").await?.unwrap();
+        let input = SearchMessages::new("search");
+        let ret =
+            search_messages_handler(Extension(user.clone()), State(state.clone()), Query(input))
+                .await?
+                .into_response();
+
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let hits = serde_json::from_slice::<Vec<SearchHit>>(&body)?;
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].message.id, msg.id);
+
+        let input = SearchMessages::new("after:someday");
+        let ret = search_messages_handler(Extension(user), State(state), Query(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::BAD_REQUEST);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn forward_message_handler_should_check_access() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+
+        // chat 2 is private with members 1, 2, 3
+        let input = CreateMessage::new("secret", &[]);
+        let msg = state.create_message(input, 2, 2).await?;
+
+        let user = state.find_user_by_email("user4@acme.org").await?.unwrap();
+        let input = ForwardMessage::new(1, "");
+        let ret = forward_message_handler(
+            Extension(user),
+            State(state.clone()),
+            Path((2, msg.id)),
+            Json(input),
+        )
+        .await
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+
+        // user3 can read chat 2 and post to chat 4
+        let user = state.find_user_by_email("user3@acme.org").await?.unwrap();
+        let input = ForwardMessage::new(4, "look");
+        let ret = forward_message_handler(
+            Extension(user.clone()),
+            State(state.clone()),
+            Path((2, msg.id)),
+            Json(input),
+        )
+        .await?
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::CREATED);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let fwd = serde_json::from_slice::<Message>(&body)?;
+        assert_eq!(fwd.forwarded.unwrap().content, "secret");
+
+        // but not to chat 3
+        let input = ForwardMessage::new(3, "");
+        let ret = forward_message_handler(
+            Extension(user),
+            State(state),
+            Path((2, msg.id)),
+            Json(input),
+        )
+        .await
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
 }
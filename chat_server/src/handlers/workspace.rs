@@ -1,11 +1,70 @@
 use axum::{extract::State, response::IntoResponse, Extension, Json};
 
-use crate::{error::AppError, models::Workspace, AppState, User};
+use chat_core::User;
+
+use crate::{error::AppError, models::UpdateWorkspace, AppState};
 
 pub(crate) async fn list_chat_users_handler(
     Extension(user): Extension<User>,
     State(state): State<AppState>,
 ) -> Result<impl IntoResponse, AppError> {
-    let users = Workspace::fetch_all_chat_users(user.ws_id as _, &state.pool).await?;
+    let users = state.fetch_chat_users(user.ws_id as _).await?;
     Ok(Json(users))
 }
+
+/// Update settings of the workspace of the user, only the owner can do it.
+pub(crate) async fn update_workspace_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<UpdateWorkspace>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .find_workspace_by_id(user.ws_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Workspace {} not found.", user.ws_id)))?;
+    if ws.owner_id != user.id {
+        return Err(AppError::WorkspaceAccessDenied(format!(
+            "User {} is not the owner of workspace {}.",
+            user.id, ws.id
+        )));
+    }
+
+    let ws = state.update_workspace(ws.id, input).await?;
+    Ok(Json(ws))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AppConfig;
+    use anyhow::{Ok, Result};
+    use axum::http::StatusCode;
+    use chat_core::Workspace;
+    use http_body_util::BodyExt;
+
+    #[tokio::test]
+    async fn update_workspace_handler_should_work() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+
+        let user = state.find_user_by_email("user1@acme.org").await?.unwrap();
+        let input = UpdateWorkspace::new(Some(300));
+        let ret = update_workspace_handler(Extension(user), State(state.clone()), Json(input))
+            .await?
+            .into_response();
+
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let ws = serde_json::from_slice::<Workspace>(&body)?;
+        assert_eq!(ws.edit_window_secs, Some(300));
+
+        let user = state.find_user_by_email("user2@acme.org").await?.unwrap();
+        let input = UpdateWorkspace::new(None);
+        let ret = update_workspace_handler(Extension(user), State(state), Json(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+}
@@ -1,26 +1,32 @@
+mod commands;
 mod config;
 mod error;
 mod handlers;
 mod middlewares;
 mod models;
-mod utils;
+mod policy;
 
 use anyhow::Context;
 use axum::{
     middleware::from_fn_with_state,
-    routing::{get, post},
+    routing::{delete, get, patch, post},
     Router,
 };
+use chat_core::{DecodingKey, EncodingKey};
+use chrono::Utc;
+use commands::CommandRegistry;
 use core::fmt;
 use error::AppError;
 use handlers::*;
 use middlewares::{set_layer, verify_token};
 use sqlx::PgPool;
-use std::{ops::Deref, sync::Arc};
+use std::{ops::Deref, sync::Arc, time::Duration};
+use tracing::warn;
 
 pub use config::AppConfig;
-pub use models::User;
-pub use utils::{DecodingKey, EncodingKey};
+
+const DISPATCH_INTERVAL: Duration = Duration::from_secs(1);
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
 
 #[derive(Debug, Clone)]
 pub(crate) struct AppState {
@@ -33,13 +39,24 @@ pub(crate) struct AppStateInner {
     pub(crate) dk: DecodingKey,
     pub(crate) ek: EncodingKey,
     pub(crate) pool: PgPool,
+    pub(crate) commands: CommandRegistry,
 }
 
 pub async fn get_router(config: AppConfig) -> Result<Router, AppError> {
     let state = AppState::try_new(config).await?;
+    spawn_scheduled_dispatcher(state.clone());
+    spawn_expired_sweeper(state.clone());
 
     let api = Router::new()
         .route("/users", get(list_chat_users_handler))
+        .route("/workspace", patch(update_workspace_handler))
+        .route("/mentions", get(list_mentions_handler))
+        .route("/search/messages", get(search_messages_handler))
+        .route("/scheduled", get(list_scheduled_messages_handler))
+        .route(
+            "/scheduled/:id",
+            patch(update_scheduled_message_handler).delete(cancel_scheduled_message_handler),
+        )
         .route("/chats", get(list_chats_handler).post(create_chat_handler))
         .route(
             "/chats/:id",
@@ -48,7 +65,42 @@ pub async fn get_router(config: AppConfig) -> Result<Router, AppError> {
                 .delete(delete_chat_handler)
                 .post(send_message_handler),
         )
+        .route("/chats/:id/members", post(add_chat_members_handler))
+        .route(
+            "/chats/:id/members/:user_id",
+            delete(remove_chat_member_handler),
+        )
+        .route(
+            "/chats/:id/pins",
+            get(list_pins_handler).post(pin_message_handler),
+        )
+        .route("/chats/:id/pins/:msg_id", delete(unpin_message_handler))
+        .route("/chats/:id/read", post(read_chat_handler))
+        .route("/chats/:id/scheduled", post(schedule_message_handler))
+        .route("/chats/:id/join", post(join_chat_handler))
+        .route("/chats/:id/leave", post(leave_chat_handler))
         .route("/chats/:id/messages", get(list_message_handler))
+        .route(
+            "/chats/:id/messages/:msg_id",
+            patch(update_message_handler).delete(delete_message_handler),
+        )
+        .route(
+            "/chats/:id/messages/:msg_id/forward",
+            post(forward_message_handler),
+        )
+        .route("/chats/:id/messages/:msg_id/votes", post(vote_poll_handler))
+        .route(
+            "/chats/:id/messages/:msg_id/replies",
+            get(list_replies_handler),
+        )
+        .route(
+            "/chats/:id/messages/:msg_id/reactions",
+            post(add_reaction_handler),
+        )
+        .route(
+            "/chats/:id/messages/:msg_id/reactions/:emoji",
+            delete(remove_reaction_handler),
+        )
         .layer(from_fn_with_state(state.clone(), verify_token))
         // routes doesn't require auth
         .route("/signin", post(signin_handler))
@@ -62,6 +114,36 @@ pub async fn get_router(config: AppConfig) -> Result<Router, AppError> {
     Ok(set_layer(app))
 }
 
+// scheduled messages and reminders are delivered by every server, they never send the
+// same one twice
+fn spawn_scheduled_dispatcher(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(DISPATCH_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = state.dispatch_scheduled_messages().await {
+                warn!("failed to dispatch scheduled messages: {}", e);
+            }
+            if let Err(e) = state.dispatch_reminders(Utc::now()).await {
+                warn!("failed to dispatch reminders: {}", e);
+            }
+        }
+    });
+}
+
+// expired messages are tombstoned by every server, each one only once
+fn spawn_expired_sweeper(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = state.sweep_expired_messages(Utc::now()).await {
+                warn!("failed to sweep expired messages: {}", e);
+            }
+        }
+    });
+}
+
 impl Deref for AppState {
     type Target = AppStateInner;
 
@@ -83,6 +165,7 @@ impl AppState {
                 dk,
                 ek,
                 pool,
+                commands: CommandRegistry::with_builtins(),
             }),
         })
     }
@@ -115,6 +198,7 @@ mod test_util {
                     dk,
                     ek,
                     pool,
+                    commands: CommandRegistry::with_builtins(),
                 }),
             };
             Ok((tdb, state))
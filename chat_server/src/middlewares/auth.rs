@@ -49,7 +49,8 @@ mod tests {
     };
     use tower::ServiceExt;
 
-    use crate::{middlewares::verify_token, AppConfig, AppState, User};
+    use crate::{middlewares::verify_token, AppConfig, AppState};
+    use chat_core::User;
 
     async fn handler(_req: Request) -> impl IntoResponse {
         (StatusCode::OK, "ok")
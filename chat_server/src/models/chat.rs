@@ -1,9 +1,47 @@
+use chat_core::{Chat, ChatType};
 use serde::{Deserialize, Serialize};
-use sqlx::PgPool;
 
-use crate::error::AppError;
-
-use super::{Chat, ChatType, ChatUser};
+use crate::{error::AppError, AppState};
+
+const MAX_PINS: i32 = 500;
+const MAX_MESSAGE_TTL_SECS: i64 = 365 * 24 * 3600;
+const MAX_TOPIC_LEN: usize = 250;
+
+// member ids are aggregated from chat_members, so the api keeps returning a plain id list
+pub(super) const CHAT_SELECT: &str = r#"
+    SELECT c.id, c.ws_id, c.name, c.type, c.topic, c.max_pins, c.message_ttl_secs, c.created_at,
+        ARRAY(
+            SELECT m.user_id FROM chat_members m WHERE m.chat_id = c.id ORDER BY m.user_id
+        ) AS members
+    FROM chats c
+"#;
+
+// chats with the unread count and the latest message for user $2, both only cover top
+// level messages, the count those of others after the user's read position
+const USER_CHAT_SELECT: &str = r#"
+    SELECT c.id, c.ws_id, c.name, c.type, c.topic, c.max_pins, c.message_ttl_secs, c.created_at,
+        ARRAY(
+            SELECT m.user_id FROM chat_members m WHERE m.chat_id = c.id ORDER BY m.user_id
+        ) AS members,
+        COALESCE(u.unread_count, 0) AS unread_count,
+        COALESCE(l.last_message, 'null'::jsonb) AS last_message
+    FROM chats c
+    LEFT JOIN chat_members me ON me.chat_id = c.id AND me.user_id = $2
+    LEFT JOIN LATERAL (
+        SELECT COUNT(*) AS unread_count
+        FROM messages m
+        WHERE me.user_id IS NOT NULL AND m.chat_id = c.id
+            AND m.parent_id IS NULL AND m.id > COALESCE(me.last_read_message_id, 0)
+            AND m.sender_id <> $2 AND m.deleted_at IS NULL
+    ) u ON TRUE
+    LEFT JOIN LATERAL (
+        SELECT to_jsonb(m) - 'content_tsv' || jsonb_build_object('images', COALESCE(m.images, '{}')) AS last_message
+        FROM messages m
+        WHERE m.chat_id = c.id AND m.parent_id IS NULL AND m.deleted_at IS NULL
+        ORDER BY m.id DESC
+        LIMIT 1
+    ) l ON TRUE
+"#;
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct CreateChat {
@@ -13,14 +51,26 @@ pub struct CreateChat {
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct UpdateChat {
+    // still sent by older clients and ignored, members are changed through the member
+    // endpoints
+    #[serde(default, rename = "members", skip_serializing)]
+    pub _members: Option<Vec<i64>>,
     pub name: Option<String>,
-    pub members: Vec<i64>,
-    pub public: bool,
+    pub public: Option<bool>,
+    pub max_pins: Option<i32>,
+    // 0 turns expiry off
+    pub message_ttl_secs: Option<i64>,
 }
 
-impl Chat {
-    pub async fn create(input: CreateChat, ws_id: u64, pool: &PgPool) -> Result<Self, AppError> {
+impl AppState {
+    pub async fn create_chat(
+        &self,
+        input: CreateChat,
+        ws_id: u64,
+        creator_id: i64,
+    ) -> Result<Chat, AppError> {
         let len = input.members.len();
         if len < 2 {
             return Err(AppError::CreateChatError(
@@ -33,7 +83,7 @@ impl Chat {
             ));
         }
 
-        let users = ChatUser::fetch_by_ids(&input.members, pool).await?;
+        let users = self.fetch_chat_users_by_ids(&input.members).await?;
         if users.len() != len {
             return Err(AppError::CreateChatError(
                 "Some members do not exist.".to_string(),
@@ -52,103 +102,208 @@ impl Chat {
             }
         };
 
-        let chat = sqlx::query_as(
+        let mut tx = self.pool.begin().await?;
+        let (id,): (i64,) = sqlx::query_as(
             r#"
-            INSERT INTO chats (ws_id, name, type, members)
-            VALUES ($1, $2, $3, $4)
-            RETURNING id, ws_id, name, type, members, created_at
+            INSERT INTO chats (ws_id, name, type)
+            VALUES ($1, $2, $3)
+            RETURNING id
             "#,
         )
         .bind(ws_id as i64)
         .bind(input.name)
         .bind(chat_type)
-        .bind(input.members)
-        .fetch_one(pool)
+        .fetch_one(&mut *tx)
         .await?;
 
-        Ok(chat)
+        sqlx::query(
+            r#"
+            INSERT INTO chat_members (chat_id, user_id, role)
+            SELECT $1, user_id, CASE WHEN user_id = $3 THEN 'owner' ELSE 'member' END::chat_member_role
+            FROM unnest($2::BIGINT[]) AS user_id
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(id)
+        .bind(&input.members)
+        .bind(creator_id)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        self.get_chat_by_id(id).await
     }
 
-    pub async fn fetch_all(ws_id: u64, pool: &PgPool) -> Result<Vec<Self>, AppError> {
-        let chats = sqlx::query_as(
+    /// Chats of a workspace as seen by a user, with unread counts and latest messages.
+    pub async fn fetch_chats(&self, ws_id: u64, user_id: i64) -> Result<Vec<Chat>, AppError> {
+        let chats = sqlx::query_as(&format!(
             r#"
-            SELECT id, ws_id, name, type, members, created_at
-            FROM chats
-            WHERE ws_id = $1
-        "#,
-        )
+            {USER_CHAT_SELECT}
+            WHERE c.ws_id = $1
+            ORDER BY c.id
+        "#
+        ))
         .bind(ws_id as i64)
-        .fetch_all(pool)
+        .bind(user_id)
+        .fetch_all(&self.pool)
         .await?;
 
         Ok(chats)
     }
 
-    pub async fn get_by_id(id: i64, pool: &PgPool) -> Result<Self, AppError> {
-        let chat = sqlx::query_as(
+    pub async fn get_user_chat(&self, id: i64, user_id: i64) -> Result<Chat, AppError> {
+        let chat = sqlx::query_as(&format!(
             r#"
-            SELECT id, ws_id, name, type, members, created_at
-            FROM chats
-            WHERE id = $1
-        "#,
-        )
+            {USER_CHAT_SELECT}
+            WHERE c.id = $1
+        "#
+        ))
         .bind(id)
-        .fetch_one(pool)
+        .bind(user_id)
+        .fetch_one(&self.pool)
         .await?;
 
         Ok(chat)
     }
 
-    pub async fn update(id: i64, input: UpdateChat, pool: &PgPool) -> Result<Self, AppError> {
-        let len = input.members.len();
-        if len < 2 {
-            return Err(AppError::UpdateChatError(
-                "Chat must have at least 2 members".to_string(),
-            ));
-        }
-        if len > 8 && input.name.is_none() {
-            return Err(AppError::UpdateChatError(
-                "Group chat with more than 8 members must have a name".to_string(),
-            ));
-        }
+    pub async fn get_chat_by_id(&self, id: i64) -> Result<Chat, AppError> {
+        let chat = sqlx::query_as(&format!(
+            r#"
+            {CHAT_SELECT}
+            WHERE c.id = $1
+        "#
+        ))
+        .bind(id)
+        .fetch_one(&self.pool)
+        .await?;
 
-        let users = ChatUser::fetch_by_ids(&input.members, pool).await?;
-        if users.len() != len {
-            return Err(AppError::UpdateChatError(
-                "Some members do not exist.".to_string(),
-            ));
-        }
+        Ok(chat)
+    }
 
-        let chat_type = match (&input.name, len) {
-            (None, 2) => ChatType::Single,
-            (None, _) => ChatType::Group,
-            (_, _) => {
-                if input.public {
-                    ChatType::PublicChannel
-                } else {
-                    ChatType::PrivateChannel
+    pub async fn find_chat_by_id(&self, id: i64) -> Result<Option<Chat>, AppError> {
+        let chat = sqlx::query_as(&format!(
+            r#"
+            {CHAT_SELECT}
+            WHERE c.id = $1
+        "#
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(chat)
+    }
+
+    /// Update name and visibility of a chat. Members are left untouched, and the chat
+    /// type only changes when a channel is explicitly switched between public and private.
+    pub async fn update_chat(&self, id: i64, input: UpdateChat) -> Result<Chat, AppError> {
+        let chat = match self.find_chat_by_id(id).await? {
+            Some(chat) => chat,
+            None => {
+                return Err(AppError::NotFound(format!(
+                    "Chat with id={} not exist.",
+                    id
+                )))
+            }
+        };
+
+        let is_channel = matches!(
+            chat.r#type,
+            ChatType::PrivateChannel | ChatType::PublicChannel
+        );
+        let chat_type = match input.public {
+            None => chat.r#type,
+            Some(_) if !is_channel => {
+                return Err(AppError::UpdateChatError(
+                    "Only channel can be public or private.".to_string(),
+                ))
+            }
+            Some(true) => ChatType::PublicChannel,
+            Some(false) => ChatType::PrivateChannel,
+        };
+
+        let name = match input.name {
+            Some(name) if name.trim().is_empty() => {
+                if is_channel {
+                    return Err(AppError::UpdateChatError(
+                        "Channel must have a name.".to_string(),
+                    ));
                 }
+                None
+            }
+            Some(name) => Some(name),
+            None => chat.name,
+        };
+
+        // lowering the limit keeps existing pins, it only stops new ones
+        let max_pins = match input.max_pins {
+            Some(n) if !(1..=MAX_PINS).contains(&n) => {
+                return Err(AppError::UpdateChatError(format!(
+                    "Max pins must be between 1 and {}.",
+                    MAX_PINS
+                )))
+            }
+            Some(n) => n,
+            None => chat.max_pins,
+        };
+
+        // only messages sent afterwards get the new lifetime
+        let message_ttl_secs = match input.message_ttl_secs {
+            Some(0) => None,
+            Some(secs) if !(1..=MAX_MESSAGE_TTL_SECS).contains(&secs) => {
+                return Err(AppError::UpdateChatError(format!(
+                    "Message ttl must be between 1 and {} seconds.",
+                    MAX_MESSAGE_TTL_SECS
+                )))
             }
+            Some(secs) => Some(secs),
+            None => chat.message_ttl_secs,
         };
 
-        let chat = sqlx::query_as(
+        sqlx::query(
             r#"
-            UPDATE chats SET name=$2, type=$3, members=$4
+            UPDATE chats SET name=$2, type=$3, max_pins=$4, message_ttl_secs=$5
             WHERE id=$1
-            RETURNING id, ws_id, name, type, members, created_at
             "#,
         )
         .bind(id)
-        .bind(input.name)
+        .bind(name)
         .bind(chat_type)
-        .bind(input.members)
-        .fetch_one(pool)
+        .bind(max_pins)
+        .bind(message_ttl_secs)
+        .execute(&self.pool)
         .await?;
 
-        Ok(chat)
+        self.get_chat_by_id(id).await
+    }
+
+    /// Set or clear the topic of a chat.
+    pub async fn set_chat_topic(&self, id: i64, topic: &str) -> Result<Chat, AppError> {
+        let topic = topic.trim();
+        if topic.chars().count() > MAX_TOPIC_LEN {
+            return Err(AppError::UpdateChatError(format!(
+                "Topic can have at most {} characters.",
+                MAX_TOPIC_LEN
+            )));
+        }
+
+        let ret = sqlx::query("UPDATE chats SET topic=$2 WHERE id=$1")
+            .bind(id)
+            .bind((!topic.is_empty()).then_some(topic))
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+        if ret < 1 {
+            return Err(AppError::NotFound(format!(
+                "Chat with id={} not exist.",
+                id
+            )));
+        }
+
+        self.get_chat_by_id(id).await
     }
 
-    pub async fn delete(id: i64, pool: &PgPool) -> Result<(), AppError> {
+    pub async fn delete_chat(&self, id: i64) -> Result<(), AppError> {
         if id == 0 {
             return Err(AppError::DeleteChatError(
                 "Chat with id=0 can not be delete".to_string(),
@@ -157,7 +312,7 @@ impl Chat {
 
         let ret = sqlx::query("DELETE FROM chats WHERE id=$1")
             .bind(id)
-            .execute(pool)
+            .execute(&self.pool)
             .await?
             .rows_affected();
 
@@ -191,7 +346,7 @@ impl CreateChat {
 
 #[cfg(test)]
 impl UpdateChat {
-    pub fn new(name: &str, members: &[i64], public: bool) -> Self {
+    pub fn new(name: &str, public: Option<bool>) -> Self {
         let name = if name.is_empty() {
             None
         } else {
@@ -199,9 +354,11 @@ impl UpdateChat {
         };
 
         Self {
+            _members: None,
             name,
-            members: members.to_vec(),
             public,
+            max_pins: None,
+            message_ttl_secs: None,
         }
     }
 }
@@ -210,16 +367,22 @@ impl UpdateChat {
 mod tests {
     use anyhow::{Ok, Result};
 
-    use crate::test_util::get_test_pool;
+    use crate::{
+        models::{
+            poll::CreatePoll, AddReaction, CreateMessage, ForwardMessage, ListMessages, PinMessage,
+            ReadChat, UpdateMessage, VotePoll,
+        },
+        AppConfig,
+    };
 
     use super::*;
 
     #[tokio::test]
     async fn create_single_chat_should_work() -> Result<()> {
-        let (_tdb, pool) = get_test_pool(None).await;
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
 
         let input = CreateChat::new("", &[1, 2], false);
-        let chat = Chat::create(input, 1, &pool).await?;
+        let chat = state.create_chat(input, 1, 1).await?;
         assert_eq!(chat.ws_id, 1);
         assert_eq!(chat.members.len(), 2);
         assert_eq!(chat.r#type, ChatType::Single);
@@ -229,10 +392,10 @@ mod tests {
 
     #[tokio::test]
     async fn create_public_named_chat_should_work() -> Result<()> {
-        let (_tdb, pool) = get_test_pool(None).await;
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
 
         let input = CreateChat::new("pub", &[1, 2, 3], true);
-        let chat = Chat::create(input, 1, &pool).await?;
+        let chat = state.create_chat(input, 1, 1).await?;
         assert_eq!(chat.ws_id, 1);
         assert_eq!(chat.name.unwrap(), "pub");
         assert_eq!(chat.members.len(), 3);
@@ -243,9 +406,9 @@ mod tests {
 
     #[tokio::test]
     async fn chat_get_by_id_should_work() -> Result<()> {
-        let (_tdb, pool) = get_test_pool(None).await;
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
 
-        let chat = Chat::get_by_id(1, &pool).await?;
+        let chat = state.get_chat_by_id(1).await?;
         assert_eq!(chat.id, 1);
         assert_eq!(chat.name.unwrap(), "general");
         assert_eq!(chat.r#type, ChatType::PublicChannel);
@@ -255,27 +418,49 @@ mod tests {
 
     #[tokio::test]
     async fn chat_fetch_all_should_work() -> Result<()> {
-        let (_tdb, pool) = get_test_pool(None).await;
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
 
-        let chats = Chat::fetch_all(1, &pool).await?;
+        let chats = state.fetch_chats(1, 1).await?;
         assert_eq!(chats.len(), 4);
         Ok(())
     }
 
     #[tokio::test]
-    async fn update_chat_to_public_named_should_work() -> Result<()> {
-        let (_tdb, pool) = get_test_pool(None).await;
+    async fn update_chat_should_not_change_type() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
 
-        let chat = Chat::get_by_id(3, &pool).await?;
+        let chat = state.get_chat_by_id(3).await?;
         assert!(chat.name.is_none());
         assert_eq!(chat.members.len(), 2);
         assert_eq!(chat.r#type, ChatType::Single);
 
         let id = 3;
-        let input = UpdateChat::new("pub", &[1, 2, 3], true);
-        let chat = Chat::update(id, input, &pool).await?;
+        let input = UpdateChat::new("pub", None);
+        let chat = state.update_chat(id, input).await?;
         assert_eq!(chat.ws_id, 1);
         assert_eq!(chat.name.unwrap(), "pub");
+        assert_eq!(chat.members.len(), 2);
+        assert_eq!(chat.r#type, ChatType::Single);
+
+        let input = UpdateChat::new("pub", Some(true));
+        let ret = state.update_chat(id, input).await;
+        assert!(matches!(ret, Err(AppError::UpdateChatError(_))));
+
+        // members of older clients are accepted and left alone
+        let input: UpdateChat = serde_json::from_str(r#"{"name": "pub", "members": [1, 2, 3]}"#)?;
+        let chat = state.update_chat(id, input).await?;
+        assert_eq!(chat.members, vec![1, 2]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_private_channel_to_public_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+
+        let input = UpdateChat::new("", Some(true));
+        let chat = state.update_chat(2, input).await?;
+        assert_eq!(chat.name.unwrap(), "private");
         assert_eq!(chat.members.len(), 3);
         assert_eq!(chat.r#type, ChatType::PublicChannel);
 
@@ -284,16 +469,55 @@ mod tests {
 
     #[tokio::test]
     async fn chat_delete_should_work() -> Result<()> {
-        let (_tdb, pool) = get_test_pool(None).await;
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
 
-        let ret = Chat::delete(1, &pool).await;
+        let ret = state.delete_chat(1).await;
         assert!(ret.is_ok());
 
-        let ret = Chat::delete(0, &pool).await;
+        let ret = state.delete_chat(0).await;
         assert!(ret.is_err());
-        let ret = Chat::delete(10, &pool).await;
+        let ret = state.delete_chat(10).await;
         assert!(ret.is_err());
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn chat_delete_should_remove_messages() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+
+        let input = CreateMessage {
+            poll: Some(CreatePoll::new(&["yes", "no"], false, false)),
+            ..CreateMessage::new("ship it @user2?", &[])
+        };
+        let msg = state.create_message(input, 2, 1).await?;
+        let option = msg.poll.as_ref().unwrap().options[0].id;
+        state
+            .vote_poll(2, msg.id, 2, VotePoll::new(&[option]))
+            .await?;
+        state
+            .create_message(CreateMessage::reply(msg.id, "in thread"), 2, 2)
+            .await?;
+        state
+            .add_reaction(2, msg.id, 2, AddReaction::new("🚀"))
+            .await?;
+        state.pin_message(2, 1, PinMessage::new(msg.id)).await?;
+        let user1 = state.find_user_by_email("user1@acme.org").await?.unwrap();
+        let input = UpdateMessage::new(Some("ship it @user3?"), None);
+        state.update_message(input, 2, msg.id, &user1).await?;
+        state.read_chat(2, 2, ReadChat::new(Some(msg.id))).await?;
+        let fwd = state
+            .forward_message(ForwardMessage::new(3, ""), 2, msg.id, 1)
+            .await?;
+
+        state.delete_chat(2).await?;
+
+        // the forward in another chat stays, without its source
+        let messages = state.list_messages(ListMessages::default(), 3).await?;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].id, fwd.id);
+        assert_eq!(messages[0].forwarded_from, None);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,395 @@
+use chat_core::{move_read_position, Chat, ChatMemberRole, ChatType};
+use serde::{Deserialize, Serialize};
+use sqlx::{Postgres, Transaction};
+
+use crate::{error::AppError, AppState};
+
+use super::chat::CHAT_SELECT;
+
+const MAX_UNNAMED_GROUP_MEMBERS: i64 = 8;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AddChatMembers {
+    pub members: Vec<i64>,
+}
+
+// read up to the latest message when no message is given
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReadChat {
+    pub message_id: Option<i64>,
+}
+
+impl AppState {
+    pub async fn chat_member_role(
+        &self,
+        id: i64,
+        user_id: i64,
+    ) -> Result<Option<ChatMemberRole>, AppError> {
+        let role: Option<(ChatMemberRole,)> =
+            sqlx::query_as("SELECT role FROM chat_members WHERE chat_id = $1 AND user_id = $2")
+                .bind(id)
+                .bind(user_id)
+                .fetch_optional(&self.pool)
+                .await?;
+
+        Ok(role.map(|(role,)| role))
+    }
+
+    /// Add members of the chat's workspace to a chat.
+    pub async fn add_chat_members(&self, id: i64, input: AddChatMembers) -> Result<Chat, AppError> {
+        if input.members.is_empty() {
+            return Err(AppError::ChatMemberError(
+                "Members can not be empty.".to_string(),
+            ));
+        }
+
+        let mut tx = self.pool.begin().await?;
+        let chat = lock_chat(id, &mut tx).await?;
+        if chat.r#type == ChatType::Single {
+            return Err(AppError::ChatMemberError(
+                "Can not change members of a single chat.".to_string(),
+            ));
+        }
+
+        let (count,): (i64,) = sqlx::query_as(
+            r#"
+            SELECT COUNT(*) FROM users
+            WHERE id = ANY($1) AND ws_id = $2
+            "#,
+        )
+        .bind(&input.members)
+        .bind(chat.ws_id)
+        .fetch_one(&mut *tx)
+        .await?;
+        let mut ids = input.members.clone();
+        ids.sort_unstable();
+        ids.dedup();
+        if count != ids.len() as i64 {
+            return Err(AppError::ChatMemberError(
+                "Some members do not exist in the workspace.".to_string(),
+            ));
+        }
+
+        sqlx::query(
+            r#"
+            INSERT INTO chat_members (chat_id, user_id)
+            SELECT $1, unnest($2::BIGINT[])
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(id)
+        .bind(&ids)
+        .execute(&mut *tx)
+        .await?;
+
+        if chat.r#type == ChatType::Group
+            && chat.name.is_none()
+            && member_count(id, &mut tx).await? > MAX_UNNAMED_GROUP_MEMBERS
+        {
+            return Err(AppError::ChatMemberError(
+                "Group chat with more than 8 members must have a name".to_string(),
+            ));
+        }
+        tx.commit().await?;
+
+        self.get_chat_by_id(id).await
+    }
+
+    /// Remove a member from a chat. The chat owner can only leave by themselves.
+    pub async fn remove_chat_member(&self, id: i64, member_id: i64) -> Result<Chat, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let chat = lock_chat(id, &mut tx).await?;
+        if self.chat_member_role(id, member_id).await? == Some(ChatMemberRole::Owner) {
+            return Err(AppError::ChatMemberError(
+                "Chat owner can not be removed.".to_string(),
+            ));
+        }
+
+        delete_member(&chat, member_id, &mut tx).await?;
+        tx.commit().await?;
+
+        self.get_chat_by_id(id).await
+    }
+
+    /// Join a public channel.
+    pub async fn join_chat(&self, id: i64, user_id: i64) -> Result<Chat, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let chat = lock_chat(id, &mut tx).await?;
+        if chat.r#type != ChatType::PublicChannel {
+            return Err(AppError::ChatMemberError(
+                "Only public channel can be joined.".to_string(),
+            ));
+        }
+
+        sqlx::query(
+            r#"
+            INSERT INTO chat_members (chat_id, user_id)
+            VALUES ($1, $2)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        self.get_chat_by_id(id).await
+    }
+
+    /// Move the read position of a member forward to a message of the chat, it never
+    /// moves back. Returns the chat as seen by the member.
+    pub async fn read_chat(
+        &self,
+        id: i64,
+        user_id: i64,
+        input: ReadChat,
+    ) -> Result<Chat, AppError> {
+        let message_id: Option<i64> = match input.message_id {
+            Some(message_id) => {
+                let found: Option<i64> =
+                    sqlx::query_scalar("SELECT id FROM messages WHERE id = $1 AND chat_id = $2")
+                        .bind(message_id)
+                        .bind(id)
+                        .fetch_optional(&self.pool)
+                        .await?;
+                if found.is_none() {
+                    return Err(AppError::NotFound(format!(
+                        "Message with id={} not exist.",
+                        message_id
+                    )));
+                }
+                found
+            }
+            None => {
+                sqlx::query_scalar("SELECT MAX(id) FROM messages WHERE chat_id = $1")
+                    .bind(id)
+                    .fetch_one(&self.pool)
+                    .await?
+            }
+        };
+
+        if let Some(message_id) = message_id {
+            move_read_position(&self.pool, id, user_id, message_id).await?;
+        }
+
+        self.get_user_chat(id, user_id).await
+    }
+
+    /// Leave a chat. Members of a single chat can not leave it.
+    pub async fn leave_chat(&self, id: i64, user_id: i64) -> Result<Chat, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let chat = lock_chat(id, &mut tx).await?;
+        delete_member(&chat, user_id, &mut tx).await?;
+        tx.commit().await?;
+
+        self.get_chat_by_id(id).await
+    }
+}
+
+// lock the chat row, so concurrent member changes of the same chat are serialized
+async fn lock_chat(id: i64, tx: &mut Transaction<'_, Postgres>) -> Result<Chat, AppError> {
+    let chat = sqlx::query_as(&format!(
+        r#"
+        {CHAT_SELECT}
+        WHERE c.id = $1
+        FOR UPDATE OF c
+        "#
+    ))
+    .bind(id)
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    match chat {
+        Some(chat) => Ok(chat),
+        None => Err(AppError::NotFound(format!(
+            "Chat with id={} not exist.",
+            id
+        ))),
+    }
+}
+
+async fn member_count(id: i64, tx: &mut Transaction<'_, Postgres>) -> Result<i64, AppError> {
+    let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM chat_members WHERE chat_id = $1")
+        .bind(id)
+        .fetch_one(&mut **tx)
+        .await?;
+
+    Ok(count)
+}
+
+async fn delete_member(
+    chat: &Chat,
+    member_id: i64,
+    tx: &mut Transaction<'_, Postgres>,
+) -> Result<(), AppError> {
+    if chat.r#type == ChatType::Single {
+        return Err(AppError::ChatMemberError(
+            "Can not change members of a single chat.".to_string(),
+        ));
+    }
+    if !chat.members.contains(&member_id) {
+        return Err(AppError::ChatMemberError(format!(
+            "User {} is not a member of chat {}.",
+            member_id, chat.id
+        )));
+    }
+    let min = if chat.r#type == ChatType::Group { 2 } else { 1 };
+    if chat.members.len() <= min {
+        return Err(AppError::ChatMemberError(format!(
+            "Chat must have at least {} members",
+            min
+        )));
+    }
+
+    sqlx::query("DELETE FROM chat_members WHERE chat_id = $1 AND user_id = $2")
+        .bind(chat.id)
+        .bind(member_id)
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+impl AddChatMembers {
+    pub fn new(members: &[i64]) -> Self {
+        Self {
+            members: members.to_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl ReadChat {
+    pub fn new(message_id: Option<i64>) -> Self {
+        Self { message_id }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{Ok, Result};
+
+    use crate::{
+        models::{CreateChat, CreateMessage},
+        AppConfig,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn chat_creator_should_be_owner() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+
+        let input = CreateChat::new("", &[2, 3, 4], false);
+        let chat = state.create_chat(input, 1, 2).await?;
+        let role = state.chat_member_role(chat.id, 2).await?;
+        assert_eq!(role, Some(ChatMemberRole::Owner));
+        let role = state.chat_member_role(chat.id, 3).await?;
+        assert_eq!(role, Some(ChatMemberRole::Member));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn add_and_remove_members_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+
+        let chat = state
+            .add_chat_members(4, AddChatMembers::new(&[2, 5]))
+            .await?;
+        assert_eq!(chat.members, vec![1, 2, 3, 4, 5]);
+        assert_eq!(chat.r#type, ChatType::Group);
+
+        let chat = state.remove_chat_member(4, 2).await?;
+        assert_eq!(chat.members, vec![1, 3, 4, 5]);
+        assert_eq!(chat.r#type, ChatType::Group);
+
+        // user3 owns the group chat
+        let ret = state.remove_chat_member(4, 3).await;
+        assert!(matches!(ret, Err(AppError::ChatMemberError(_))));
+
+        let ret = state.add_chat_members(4, AddChatMembers::new(&[0])).await;
+        assert!(matches!(ret, Err(AppError::ChatMemberError(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn change_members_of_single_chat_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+
+        let ret = state.add_chat_members(3, AddChatMembers::new(&[3])).await;
+        assert!(matches!(ret, Err(AppError::ChatMemberError(_))));
+        let ret = state.remove_chat_member(3, 2).await;
+        assert!(matches!(ret, Err(AppError::ChatMemberError(_))));
+        let ret = state.leave_chat(3, 1).await;
+        assert!(matches!(ret, Err(AppError::ChatMemberError(_))));
+
+        let chat = state.get_chat_by_id(3).await?;
+        assert_eq!(chat.members, vec![1, 2]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn join_and_leave_public_channel_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+
+        let chat = state.leave_chat(1, 4).await?;
+        assert!(!chat.members.contains(&4));
+        assert_eq!(chat.r#type, ChatType::PublicChannel);
+
+        let chat = state.join_chat(1, 4).await?;
+        assert!(chat.members.contains(&4));
+
+        let ret = state.join_chat(2, 4).await;
+        assert!(matches!(ret, Err(AppError::ChatMemberError(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn read_chat_should_update_unread_count() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+
+        let mut ids = vec![];
+        for i in 0..3 {
+            let input = CreateMessage::new(&format!("message {}", i), &[]);
+            ids.push(state.create_message(input, 3, 1).await?.id);
+        }
+        // own messages are never unread
+        state
+            .create_message(CreateMessage::new("mine", &[]), 3, 2)
+            .await?;
+        // neither are thread replies, like they are never the last message
+        state
+            .create_message(CreateMessage::reply(ids[0], "in thread"), 3, 1)
+            .await?;
+
+        let chat = state.get_user_chat(3, 2).await?;
+        assert_eq!(chat.unread_count, 3);
+        assert_eq!(chat.last_message.unwrap().content, "mine");
+
+        let chat = state.read_chat(3, 2, ReadChat::new(Some(ids[1]))).await?;
+        assert_eq!(chat.unread_count, 1);
+
+        // the read position does not move back
+        let chat = state.read_chat(3, 2, ReadChat::new(Some(ids[0]))).await?;
+        assert_eq!(chat.unread_count, 1);
+
+        let chat = state.read_chat(3, 2, ReadChat::new(None)).await?;
+        assert_eq!(chat.unread_count, 0);
+
+        let ret = state.read_chat(1, 2, ReadChat::new(Some(ids[0]))).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        let chats = state.fetch_chats(1, 1).await?;
+        assert_eq!(chats.len(), 4);
+        assert_eq!(chats[2].unread_count, 1);
+        assert!(chats[0].last_message.is_none());
+
+        Ok(())
+    }
+}
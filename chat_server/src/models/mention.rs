@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+
+use chat_core::{Mention, MentionKind};
+use serde::{Deserialize, Serialize};
+use sqlx::{Postgres, Transaction};
+
+use crate::{error::AppError, AppState};
+
+use super::message::MESSAGE_FIELDS;
+
+const DEFAULT_PAGE_SIZE: u64 = 20;
+const MAX_PAGE_SIZE: u64 = 100;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ListMentions {
+    pub before: Option<i64>,
+    pub limit: Option<u64>,
+}
+
+impl AppState {
+    /// Unread mentions of a user across chats, newest first. A mention is unread until
+    /// the user's read position in the chat passes the message.
+    pub async fn list_mentions(
+        &self,
+        input: ListMentions,
+        user_id: i64,
+    ) -> Result<Vec<Mention>, AppError> {
+        let limit = match input.limit {
+            Some(0) => {
+                return Err(AppError::ListMessagesError(
+                    "Limit must be greater than 0.".to_string(),
+                ))
+            }
+            Some(limit) => limit.min(MAX_PAGE_SIZE),
+            None => DEFAULT_PAGE_SIZE,
+        };
+
+        let mentions = sqlx::query_as(&format!(
+            r#"
+            SELECT mm.kind, m.*
+            FROM message_mentions mm
+            JOIN (SELECT {MESSAGE_FIELDS} FROM messages) m ON m.id = mm.message_id
+            JOIN chat_members cm ON cm.chat_id = m.chat_id AND cm.user_id = mm.user_id
+            WHERE mm.user_id = $1 AND m.deleted_at IS NULL
+                AND m.id > COALESCE(cm.last_read_message_id, 0)
+                AND ($2::BIGINT IS NULL OR m.id < $2)
+            ORDER BY m.id DESC
+            LIMIT $3
+            "#,
+        ))
+        .bind(user_id)
+        .bind(input.before)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(mentions)
+    }
+}
+
+/// Store mentions in the content of a message, which are resolved against the members
+/// of its chat. The sender is never mentioned, and existing mentions are kept.
+pub(super) async fn save_mentions(
+    tx: &mut Transaction<'_, Postgres>,
+    chat_id: i64,
+    message_id: i64,
+    sender_id: i64,
+    content: &str,
+) -> Result<(), AppError> {
+    let names = parse_mentions(content);
+    if names.is_empty() {
+        return Ok(());
+    }
+
+    // members are addressed by the name part of their email
+    let members: Vec<(i64, String)> = sqlx::query_as(
+        r#"
+        SELECT u.id, lower(split_part(u.email, '@', 1))
+        FROM chat_members cm
+        JOIN users u ON u.id = cm.user_id
+        WHERE cm.chat_id = $1 AND cm.user_id <> $2
+        "#,
+    )
+    .bind(chat_id)
+    .bind(sender_id)
+    .fetch_all(&mut **tx)
+    .await?;
+
+    let mut mentioned: HashMap<i64, MentionKind> = HashMap::new();
+    for name in &names {
+        let kind = match name.as_str() {
+            "channel" => MentionKind::Channel,
+            "here" => MentionKind::Here,
+            _ => MentionKind::User,
+        };
+        for (id, _) in members
+            .iter()
+            .filter(|(_, n)| kind != MentionKind::User || n == name)
+        {
+            mentioned
+                .entry(*id)
+                .and_modify(|k| {
+                    if kind < *k {
+                        *k = kind
+                    }
+                })
+                .or_insert(kind);
+        }
+    }
+    if mentioned.is_empty() {
+        return Ok(());
+    }
+
+    let (user_ids, kinds): (Vec<i64>, Vec<MentionKind>) = mentioned.into_iter().unzip();
+    sqlx::query(
+        r#"
+        INSERT INTO message_mentions (message_id, user_id, kind)
+        SELECT $1, * FROM unnest($2::BIGINT[], $3::mention_kind[])
+        ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(message_id)
+    .bind(user_ids)
+    .bind(kinds)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+// lowercased names after `@`, which starts a word and is made of letters, digits,
+// `.`, `_` and `-`, e.g. `@channel`, `@here`, `@alice.b`
+fn parse_mentions(content: &str) -> Vec<String> {
+    let mut names = vec![];
+    let mut prev: Option<char> = None;
+    let mut chars = content.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let starts_word = !matches!(prev, Some(p) if p.is_alphanumeric() || p == '@');
+        prev = Some(c);
+        if c != '@' || !starts_word {
+            continue;
+        }
+
+        let start = i + 1;
+        let mut end = start;
+        while let Some(&(j, c)) = chars.peek() {
+            if !(c.is_alphanumeric() || matches!(c, '.' | '_' | '-')) {
+                break;
+            }
+            end = j + c.len_utf8();
+            prev = Some(c);
+            chars.next();
+        }
+        // a trailing dot ends the sentence rather than the name
+        let name = content[start..end].trim_end_matches('.').to_lowercase();
+        if !name.is_empty() && !names.contains(&name) {
+            names.push(name);
+        }
+    }
+
+    names
+}
+
+#[cfg(test)]
+impl ListMentions {
+    pub fn new(before: Option<i64>, limit: Option<u64>) -> Self {
+        Self { before, limit }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{Ok, Result};
+
+    use crate::{models::CreateMessage, AppConfig};
+
+    use super::*;
+
+    #[test]
+    fn parse_mentions_should_work() {
+        assert_eq!(
+            parse_mentions("hi @User2, @here and @channel. ping @user3."),
+            vec!["user2", "here", "channel", "user3"]
+        );
+        assert_eq!(
+            parse_mentions("mail me at user1@acme.org @ @@x"),
+            Vec::<String>::new()
+        );
+        assert_eq!(parse_mentions("(@a.b) @a.b"), vec!["a.b"]);
+    }
+
+    #[tokio::test]
+    async fn mentions_should_be_saved_and_listed() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+
+        // chat 4 has members 1, 3, 4
+        let input = CreateMessage::new("@user3 @user2 @user1 look", &[]);
+        let msg1 = state.create_message(input, 4, 1).await?;
+        let input = CreateMessage::new("@channel @user4", &[]);
+        let msg2 = state.create_message(input, 4, 1).await?;
+
+        let mentions = state.list_mentions(ListMentions::default(), 3).await?;
+        assert_eq!(mentions.len(), 2);
+        assert_eq!(mentions[0].message.id, msg2.id);
+        assert_eq!(mentions[0].kind, MentionKind::Channel);
+        assert_eq!(mentions[1].message.id, msg1.id);
+        assert_eq!(mentions[1].kind, MentionKind::User);
+
+        let mentions = state.list_mentions(ListMentions::default(), 4).await?;
+        assert_eq!(mentions.len(), 1);
+        assert_eq!(mentions[0].kind, MentionKind::User);
+
+        // neither the sender nor non-members are mentioned
+        assert!(state
+            .list_mentions(ListMentions::default(), 1)
+            .await?
+            .is_empty());
+        assert!(state
+            .list_mentions(ListMentions::default(), 2)
+            .await?
+            .is_empty());
+
+        let input = ListMentions::new(Some(msg2.id), None);
+        let mentions = state.list_mentions(input, 3).await?;
+        assert_eq!(mentions.len(), 1);
+
+        // read mentions are left out
+        sqlx::query(
+            "UPDATE chat_members SET last_read_message_id = $1 WHERE chat_id = 4 AND user_id = 3",
+        )
+        .bind(msg1.id)
+        .execute(&state.pool)
+        .await?;
+        let mentions = state.list_mentions(ListMentions::default(), 3).await?;
+        assert_eq!(mentions.len(), 1);
+        assert_eq!(mentions[0].message.id, msg2.id);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1253 @@
+use chat_core::{Message, User};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Postgres, Transaction};
+
+use crate::{error::AppError, AppState};
+
+use super::{
+    mention::save_mentions,
+    poll::{fetch_poll, save_poll, validate_poll, CreatePoll},
+};
+
+const DEFAULT_PAGE_SIZE: u64 = 20;
+const MAX_PAGE_SIZE: u64 = 100;
+
+pub(super) const MESSAGE_FIELDS: &str = "id, chat_id, sender_id, parent_id, forwarded_from, \
+    quoted_id, content, images, edited_at, deleted_at, client_msg_id, expires_at, created_at";
+
+const MAX_CLIENT_MSG_ID_LEN: usize = 64;
+// expired messages tombstoned in one run of the sweeper
+const SWEEP_BATCH_SIZE: i64 = 500;
+
+// the forwarded and quoted messages of `messages` as json
+const MESSAGE_REFS: &str = r#"
+    COALESCE((
+        SELECT jsonb_build_object(
+            'id', s.id, 'chat_id', s.chat_id, 'sender_id', s.sender_id, 'content', s.content,
+            'images', COALESCE(s.images, '{}'), 'deleted_at', s.deleted_at, 'created_at', s.created_at
+        )
+        FROM messages s WHERE s.id = messages.forwarded_from
+    ), 'null') AS forwarded,
+    COALESCE((
+        SELECT jsonb_build_object(
+            'id', s.id, 'chat_id', s.chat_id, 'sender_id', s.sender_id, 'content', s.content,
+            'images', COALESCE(s.images, '{}'), 'deleted_at', s.deleted_at, 'created_at', s.created_at
+        )
+        FROM messages s WHERE s.id = messages.quoted_id
+    ), 'null') AS quote
+"#;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CreateMessage {
+    pub content: String,
+    #[serde(default)]
+    pub images: Vec<String>,
+    // reply in the thread of this message
+    #[serde(default)]
+    pub parent_id: Option<i64>,
+    // quote a message of the same chat
+    #[serde(default)]
+    pub quoted_id: Option<i64>,
+    // a retried send with the same key returns the message sent first
+    #[serde(default)]
+    pub client_msg_id: Option<String>,
+    // the chat's message ttl still applies when it ends earlier
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    // the content is the question of the poll
+    #[serde(default)]
+    pub poll: Option<CreatePoll>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ForwardMessage {
+    // the chat to forward to
+    pub chat_id: i64,
+    // an optional comment of the forwarder
+    #[serde(default)]
+    pub content: String,
+}
+
+// fields which are not given are left unchanged
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpdateMessage {
+    pub content: Option<String>,
+    pub images: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ListMessages {
+    pub before: Option<i64>,
+    pub after: Option<i64>,
+    pub limit: Option<u64>,
+}
+
+impl AppState {
+    pub async fn create_message(
+        &self,
+        input: CreateMessage,
+        chat_id: i64,
+        user_id: i64,
+    ) -> Result<Message, AppError> {
+        if let Some(key) = &input.client_msg_id {
+            check_client_msg_id(key).map_err(AppError::CreateMessageError)?;
+            if let Some(message) = self.find_sent_message(chat_id, user_id, key).await? {
+                return Ok(message);
+            }
+        }
+        let mut tx = self.pool.begin().await?;
+        check_new_message(&mut tx, &input, chat_id).await?;
+
+        let key = input.client_msg_id.clone();
+        let message = match insert_message(&mut tx, input, chat_id, user_id).await {
+            Ok(message) => message,
+            // a concurrent retry sent it first
+            Err(AppError::SqlxError(e))
+                if e.as_database_error()
+                    .is_some_and(|e| e.is_unique_violation()) =>
+            {
+                let key = key.unwrap_or_default();
+                drop(tx);
+                return self
+                    .find_sent_message(chat_id, user_id, &key)
+                    .await?
+                    .ok_or(AppError::SqlxError(e));
+            }
+            Err(e) => return Err(e),
+        };
+        tx.commit().await?;
+
+        Ok(message)
+    }
+
+    // the message a sender sent to the chat with a client message id
+    async fn find_sent_message(
+        &self,
+        chat_id: i64,
+        user_id: i64,
+        client_msg_id: &str,
+    ) -> Result<Option<Message>, AppError> {
+        let message = sqlx::query_as(&format!(
+            r#"
+            SELECT {MESSAGE_FIELDS}
+            FROM messages
+            WHERE chat_id = $1 AND sender_id = $2 AND client_msg_id = $3
+            "#,
+        ))
+        .bind(chat_id)
+        .bind(user_id)
+        .bind(client_msg_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(message)
+    }
+
+    /// Forward a message into another chat. The new message only points to the
+    /// original, forwarding a forward points to the same original.
+    pub async fn forward_message(
+        &self,
+        input: ForwardMessage,
+        chat_id: i64,
+        id: i64,
+        user_id: i64,
+    ) -> Result<Message, AppError> {
+        let source: Option<(Option<i64>, Option<DateTime<Utc>>)> = sqlx::query_as(
+            "SELECT forwarded_from, deleted_at FROM messages WHERE id = $1 AND chat_id = $2",
+        )
+        .bind(id)
+        .bind(chat_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        let original = match source {
+            None => {
+                return Err(AppError::NotFound(format!(
+                    "Message with id={} not exist.",
+                    id
+                )))
+            }
+            Some((_, Some(_))) => {
+                return Err(AppError::CreateMessageError(format!(
+                    "Message {} has been deleted.",
+                    id
+                )))
+            }
+            Some((forwarded_from, None)) => forwarded_from.unwrap_or(id),
+        };
+
+        let mut tx = self.pool.begin().await?;
+        let (new_id,): (i64,) = sqlx::query_as(
+            r#"
+            INSERT INTO messages (chat_id, sender_id, content, images, forwarded_from, expires_at)
+            VALUES ($1, $2, $3, '{}', $4, (
+                SELECT NOW() + c.message_ttl_secs * INTERVAL '1 second' FROM chats c WHERE c.id = $1
+            ))
+            RETURNING id
+            "#,
+        )
+        .bind(input.chat_id)
+        .bind(user_id)
+        .bind(&input.content)
+        .bind(original)
+        .fetch_one(&mut *tx)
+        .await?;
+        save_mentions(&mut tx, input.chat_id, new_id, user_id, &input.content).await?;
+        tx.commit().await?;
+
+        self.get_message(input.chat_id, new_id).await
+    }
+
+    /// A message with the messages it forwards or quotes.
+    pub async fn get_message(&self, chat_id: i64, id: i64) -> Result<Message, AppError> {
+        let message = sqlx::query_as(&format!(
+            r#"
+            SELECT {MESSAGE_FIELDS}, {MESSAGE_REFS}
+            FROM messages
+            WHERE id = $1 AND chat_id = $2
+            "#,
+        ))
+        .bind(id)
+        .bind(chat_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        message.ok_or_else(|| AppError::NotFound(format!("Message with id={} not exist.", id)))
+    }
+
+    /// Edit a message by its sender. The replaced revision is kept in `message_edits`.
+    pub async fn update_message(
+        &self,
+        input: UpdateMessage,
+        chat_id: i64,
+        id: i64,
+        user: &User,
+    ) -> Result<Message, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let row: Option<EditableMessage> = sqlx::query_as(
+            r#"
+            SELECT m.sender_id, m.content, m.images, m.forwarded_from, m.created_at,
+                m.deleted_at, w.edit_window_secs
+            FROM messages m
+            JOIN chats c ON c.id = m.chat_id
+            JOIN workspaces w ON w.id = c.ws_id
+            WHERE m.id = $1 AND m.chat_id = $2
+            FOR UPDATE OF m
+            "#,
+        )
+        .bind(id)
+        .bind(chat_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(EditableMessage {
+            sender_id,
+            content,
+            images,
+            forwarded_from,
+            created_at,
+            deleted_at,
+            edit_window_secs,
+        }) = row
+        else {
+            return Err(AppError::NotFound(format!(
+                "Message with id={} not exist.",
+                id
+            )));
+        };
+
+        if sender_id != user.id {
+            return Err(AppError::MessageAccessDenied(format!(
+                "User {} can not edit message {}.",
+                user.id, id
+            )));
+        }
+        if deleted_at.is_some() {
+            return Err(AppError::UpdateMessageError(format!(
+                "Message {} has been deleted.",
+                id
+            )));
+        }
+        if let Some(secs) = edit_window_secs {
+            if (Utc::now() - created_at).num_seconds() > secs {
+                return Err(AppError::UpdateMessageError(format!(
+                    "Message can only be edited within {} seconds.",
+                    secs
+                )));
+            }
+        }
+
+        let images = images.unwrap_or_default();
+        let new_content = input.content.unwrap_or_else(|| content.clone());
+        let new_images = input.images.unwrap_or_else(|| images.clone());
+        // the comment of a forward may be empty
+        if forwarded_from.is_none() {
+            validate_message(&new_content, &new_images).map_err(AppError::UpdateMessageError)?;
+        }
+
+        sqlx::query(
+            r#"
+            INSERT INTO message_edits (message_id, editor_id, content, images)
+            VALUES ($1, $2, $3, $4)
+            "#,
+        )
+        .bind(id)
+        .bind(user.id)
+        .bind(content)
+        .bind(images)
+        .execute(&mut *tx)
+        .await?;
+
+        let message: Message = sqlx::query_as(&format!(
+            r#"
+            UPDATE messages SET content = $2, images = $3, edited_at = NOW()
+            WHERE id = $1
+            RETURNING {MESSAGE_FIELDS}
+            "#,
+        ))
+        .bind(id)
+        .bind(new_content)
+        .bind(new_images)
+        .fetch_one(&mut *tx)
+        .await?;
+        // users newly mentioned by the edit are notified as well
+        save_mentions(&mut tx, chat_id, id, user.id, &message.content).await?;
+        tx.commit().await?;
+
+        Ok(message)
+    }
+
+    /// Retract a message by its sender, or by a user who can admin the chat. The row is
+    /// kept as a tombstone with blanked content, and its reactions, mentions and pin
+    /// are dropped. Its edit history is kept.
+    pub async fn delete_message(
+        &self,
+        chat_id: i64,
+        id: i64,
+        user: &User,
+        can_admin: bool,
+    ) -> Result<Message, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let row: Option<(i64, Option<DateTime<Utc>>)> = sqlx::query_as(
+            r#"
+            SELECT sender_id, deleted_at
+            FROM messages
+            WHERE id = $1 AND chat_id = $2
+            FOR UPDATE
+            "#,
+        )
+        .bind(id)
+        .bind(chat_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some((sender_id, deleted_at)) = row else {
+            return Err(AppError::NotFound(format!(
+                "Message with id={} not exist.",
+                id
+            )));
+        };
+
+        if sender_id != user.id && !can_admin {
+            return Err(AppError::MessageAccessDenied(format!(
+                "User {} can not delete message {}.",
+                user.id, id
+            )));
+        }
+
+        // retracting twice leaves the tombstone as it is
+        if deleted_at.is_none() {
+            clear_message_data(&mut tx, &[id]).await?;
+        }
+        let message = sqlx::query_as(&format!(
+            r#"
+            UPDATE messages
+            SET content = '', images = '{{}}', deleted_at = COALESCE(deleted_at, NOW())
+            WHERE id = $1
+            RETURNING {MESSAGE_FIELDS}
+            "#,
+        ))
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(message)
+    }
+
+    /// Tombstone messages which expired at `now` the same way as retracted ones, keeping
+    /// their edit history, and return how many were swept. The notify trigger sends a
+    /// deletion event for each.
+    pub async fn sweep_expired_messages(&self, now: DateTime<Utc>) -> Result<usize, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let ids: Vec<i64> = sqlx::query_scalar(
+            r#"
+            SELECT id FROM messages
+            WHERE expires_at <= $1 AND deleted_at IS NULL
+            ORDER BY expires_at
+            LIMIT $2
+            FOR UPDATE SKIP LOCKED
+            "#,
+        )
+        .bind(now)
+        .bind(SWEEP_BATCH_SIZE)
+        .fetch_all(&mut *tx)
+        .await?;
+        if ids.is_empty() {
+            return Ok(0);
+        }
+
+        clear_message_data(&mut tx, &ids).await?;
+        sqlx::query(
+            r#"
+            UPDATE messages
+            SET content = '', images = '{}', deleted_at = expires_at
+            WHERE id = ANY($1)
+            "#,
+        )
+        .bind(&ids)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(ids.len())
+    }
+
+    /// List messages of a chat, newest first. `before` and `after` are message ids
+    /// used as cursors, so a client can page back through history or catch up on
+    /// newer messages.
+    pub async fn list_messages(
+        &self,
+        input: ListMessages,
+        chat_id: i64,
+    ) -> Result<Vec<Message>, AppError> {
+        self.fetch_messages(input, chat_id, None).await
+    }
+
+    /// List replies in the thread of a message, newest first, paged the same way as
+    /// `list_messages`.
+    pub async fn list_replies(
+        &self,
+        input: ListMessages,
+        chat_id: i64,
+        parent_id: i64,
+    ) -> Result<Vec<Message>, AppError> {
+        let found: Option<i64> =
+            sqlx::query_scalar("SELECT id FROM messages WHERE id = $1 AND chat_id = $2")
+                .bind(parent_id)
+                .bind(chat_id)
+                .fetch_optional(&self.pool)
+                .await?;
+        if found.is_none() {
+            return Err(AppError::NotFound(format!(
+                "Message with id={} not exist.",
+                parent_id
+            )));
+        }
+
+        self.fetch_messages(input, chat_id, Some(parent_id)).await
+    }
+
+    // top level messages with their thread summary, or replies of a thread
+    async fn fetch_messages(
+        &self,
+        input: ListMessages,
+        chat_id: i64,
+        parent_id: Option<i64>,
+    ) -> Result<Vec<Message>, AppError> {
+        let limit = match input.limit {
+            Some(0) => {
+                return Err(AppError::ListMessagesError(
+                    "Limit must be greater than 0.".to_string(),
+                ))
+            }
+            Some(limit) => limit.min(MAX_PAGE_SIZE),
+            None => DEFAULT_PAGE_SIZE,
+        };
+        let before = match input.before {
+            Some(id) => Some(self.message_cursor(chat_id, id).await?),
+            None => None,
+        };
+        let after = match input.after {
+            Some(id) => Some(self.message_cursor(chat_id, id).await?),
+            None => None,
+        };
+        if let (Some(before), Some(after)) = (before, after) {
+            if before <= after {
+                return Err(AppError::ListMessagesError(
+                    "Cursor before must be newer than after.".to_string(),
+                ));
+            }
+        }
+
+        // when only `after` is given, walk forward from the cursor so that the page
+        // right after it is returned, then flip it back to newest first
+        let ascending = input.after.is_some() && input.before.is_none();
+        let order = if ascending { "ASC" } else { "DESC" };
+        let sql = format!(
+            r#"
+            SELECT {MESSAGE_FIELDS}, r.reply_count, r.last_reply_at, e.reactions, {MESSAGE_REFS},
+                COALESCE(poll_json(messages.id), 'null') AS poll
+            FROM messages
+            LEFT JOIN LATERAL (
+                SELECT COUNT(*) AS reply_count, MAX(t.created_at) AS last_reply_at
+                FROM messages t
+                WHERE t.parent_id = messages.id AND t.deleted_at IS NULL
+            ) r ON TRUE
+            LEFT JOIN LATERAL (
+                SELECT COALESCE(
+                    json_agg(json_build_object('emoji', g.emoji, 'user_ids', g.user_ids) ORDER BY g.first_at, g.emoji),
+                    '[]'
+                ) AS reactions
+                FROM (
+                    SELECT emoji, array_agg(user_id ORDER BY created_at, user_id) AS user_ids, MIN(created_at) AS first_at
+                    FROM message_reactions
+                    WHERE message_id = messages.id
+                    GROUP BY emoji
+                ) g
+            ) e ON TRUE
+            WHERE chat_id = $1 AND parent_id IS NOT DISTINCT FROM $5
+            AND ($2::timestamptz IS NULL OR (created_at, id) < ($2, $3))
+            AND ($6::timestamptz IS NULL OR (created_at, id) > ($6, $7))
+            ORDER BY created_at {order}, id {order}
+            LIMIT $4
+            "#,
+        );
+
+        let mut messages: Vec<Message> = sqlx::query_as(&sql)
+            .bind(chat_id)
+            .bind(before.map(|(t, _)| t))
+            .bind(before.map(|(_, id)| id))
+            .bind(limit as i64)
+            .bind(parent_id)
+            .bind(after.map(|(t, _)| t))
+            .bind(after.map(|(_, id)| id))
+            .fetch_all(&self.pool)
+            .await?;
+        if ascending {
+            messages.reverse();
+        }
+
+        Ok(messages)
+    }
+
+    // position of a message of the chat in the pages, which are ordered by it
+    async fn message_cursor(
+        &self,
+        chat_id: i64,
+        id: i64,
+    ) -> Result<(DateTime<Utc>, i64), AppError> {
+        let created_at: Option<DateTime<Utc>> =
+            sqlx::query_scalar("SELECT created_at FROM messages WHERE id = $1 AND chat_id = $2")
+                .bind(id)
+                .bind(chat_id)
+                .fetch_optional(&self.pool)
+                .await?;
+
+        created_at.map(|t| (t, id)).ok_or_else(|| {
+            AppError::ListMessagesError(format!(
+                "Cursor message {} not exist in chat {}.",
+                id, chat_id
+            ))
+        })
+    }
+}
+
+// a message locked for editing, with the edit window of its workspace
+#[derive(Debug, FromRow)]
+struct EditableMessage {
+    sender_id: i64,
+    content: String,
+    images: Option<Vec<String>>,
+    forwarded_from: Option<i64>,
+    created_at: DateTime<Utc>,
+    deleted_at: Option<DateTime<Utc>>,
+    edit_window_secs: Option<i64>,
+}
+
+/// Checks of a message before it is sent, which may fail again when a scheduled
+/// message is delivered.
+pub(super) async fn check_new_message(
+    tx: &mut Transaction<'_, Postgres>,
+    input: &CreateMessage,
+    chat_id: i64,
+) -> Result<(), AppError> {
+    validate_message(&input.content, &input.images).map_err(AppError::CreateMessageError)?;
+    if let Some(poll) = &input.poll {
+        validate_poll(poll).map_err(AppError::CreateMessageError)?;
+    }
+    if input.expires_at.is_some_and(|t| t <= Utc::now()) {
+        return Err(AppError::CreateMessageError(
+            "Message must expire in the future.".to_string(),
+        ));
+    }
+    if let Some(parent_id) = input.parent_id {
+        check_thread_parent(tx, chat_id, parent_id).await?;
+    }
+    if let Some(quoted_id) = input.quoted_id {
+        check_quote(tx, chat_id, quoted_id).await?;
+    }
+
+    Ok(())
+}
+
+// quotes point to a live message of the same chat
+async fn check_quote(
+    tx: &mut Transaction<'_, Postgres>,
+    chat_id: i64,
+    quoted_id: i64,
+) -> Result<(), AppError> {
+    let deleted: Option<bool> = sqlx::query_scalar(
+        "SELECT deleted_at IS NOT NULL FROM messages WHERE id = $1 AND chat_id = $2",
+    )
+    .bind(quoted_id)
+    .bind(chat_id)
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    match deleted {
+        None => Err(AppError::CreateMessageError(format!(
+            "Quoted message {} not exist in chat {}.",
+            quoted_id, chat_id
+        ))),
+        Some(true) => Err(AppError::CreateMessageError(format!(
+            "Quoted message {} has been deleted.",
+            quoted_id
+        ))),
+        Some(false) => Ok(()),
+    }
+}
+
+// replies go to a live top level message of the same chat
+async fn check_thread_parent(
+    tx: &mut Transaction<'_, Postgres>,
+    chat_id: i64,
+    parent_id: i64,
+) -> Result<(), AppError> {
+    let parent: Option<(Option<i64>, Option<DateTime<Utc>>)> =
+        sqlx::query_as("SELECT parent_id, deleted_at FROM messages WHERE id = $1 AND chat_id = $2")
+            .bind(parent_id)
+            .bind(chat_id)
+            .fetch_optional(&mut **tx)
+            .await?;
+
+    match parent {
+        None => Err(AppError::CreateMessageError(format!(
+            "Parent message {} not exist in chat {}.",
+            parent_id, chat_id
+        ))),
+        Some((Some(_), _)) => Err(AppError::CreateMessageError(
+            "Can not reply to a reply, threads are not nested.".to_string(),
+        )),
+        Some((_, Some(_))) => Err(AppError::CreateMessageError(format!(
+            "Parent message {} has been deleted.",
+            parent_id
+        ))),
+        Some(_) => Ok(()),
+    }
+}
+
+/// Send a checked message with its mentions.
+pub(super) async fn insert_message(
+    tx: &mut Transaction<'_, Postgres>,
+    input: CreateMessage,
+    chat_id: i64,
+    user_id: i64,
+) -> Result<Message, AppError> {
+    let mut message: Message = sqlx::query_as(&format!(
+        r#"
+        INSERT INTO messages
+            (chat_id, sender_id, content, images, parent_id, quoted_id, client_msg_id, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, (
+            SELECT LEAST($8, NOW() + c.message_ttl_secs * INTERVAL '1 second')
+            FROM chats c WHERE c.id = $1
+        ))
+        RETURNING {MESSAGE_FIELDS}
+        "#,
+    ))
+    .bind(chat_id)
+    .bind(user_id)
+    .bind(input.content)
+    .bind(input.images)
+    .bind(input.parent_id)
+    .bind(input.quoted_id)
+    .bind(input.client_msg_id)
+    .bind(input.expires_at)
+    .fetch_one(&mut **tx)
+    .await?;
+    save_mentions(tx, chat_id, message.id, user_id, &message.content).await?;
+    if let Some(poll) = &input.poll {
+        save_poll(tx, message.id, poll).await?;
+        message.poll = fetch_poll(tx, message.id).await?;
+    }
+
+    Ok(message)
+}
+
+// data attached to messages which goes away with their content, edits are kept for
+// compliance
+async fn clear_message_data(
+    tx: &mut Transaction<'_, Postgres>,
+    ids: &[i64],
+) -> Result<(), AppError> {
+    for table in [
+        "message_reactions",
+        "message_mentions",
+        "chat_pins",
+        "poll_votes",
+        "poll_options",
+        "polls",
+    ] {
+        sqlx::query(&format!("DELETE FROM {table} WHERE message_id = ANY($1)"))
+            .bind(ids)
+            .execute(&mut **tx)
+            .await?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn check_client_msg_id(key: &str) -> Result<(), String> {
+    if key.is_empty() || key.len() > MAX_CLIENT_MSG_ID_LEN {
+        return Err(format!(
+            "Client message id must have 1 to {} characters.",
+            MAX_CLIENT_MSG_ID_LEN
+        ));
+    }
+
+    Ok(())
+}
+
+fn validate_message(content: &str, images: &[String]) -> Result<(), String> {
+    if content.trim().is_empty() && images.is_empty() {
+        return Err("Message must have content or images.".to_string());
+    }
+    if images.iter().any(|s| s.trim().is_empty()) {
+        return Err("Image url can not be empty.".to_string());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+impl CreateMessage {
+    pub fn new(content: &str, images: &[&str]) -> Self {
+        Self {
+            content: content.to_string(),
+            images: images.iter().map(|s| s.to_string()).collect(),
+            parent_id: None,
+            quoted_id: None,
+            client_msg_id: None,
+            expires_at: None,
+            poll: None,
+        }
+    }
+
+    pub fn reply(parent_id: i64, content: &str) -> Self {
+        Self {
+            parent_id: Some(parent_id),
+            ..Self::new(content, &[])
+        }
+    }
+}
+
+#[cfg(test)]
+impl ForwardMessage {
+    pub fn new(chat_id: i64, content: &str) -> Self {
+        Self {
+            chat_id,
+            content: content.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl UpdateMessage {
+    pub fn new(content: Option<&str>, images: Option<&[&str]>) -> Self {
+        Self {
+            content: content.map(|s| s.to_string()),
+            images: images.map(|v| v.iter().map(|s| s.to_string()).collect()),
+        }
+    }
+}
+
+#[cfg(test)]
+impl ListMessages {
+    pub fn new(before: Option<i64>, after: Option<i64>, limit: Option<u64>) -> Self {
+        Self {
+            before,
+            after,
+            limit,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{Ok, Result};
+    use chrono::Duration;
+
+    use crate::{
+        models::{AddReaction, UpdateChat, UpdateWorkspace},
+        AppConfig,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn create_message_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+
+        let input = CreateMessage::new("hello world", &["/files/1/abc.png"]);
+        let msg = state.create_message(input, 1, 1).await?;
+        assert_eq!(msg.chat_id, 1);
+        assert_eq!(msg.sender_id, 1);
+        assert_eq!(msg.content, "hello world");
+        assert_eq!(msg.images.len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_message_with_client_msg_id_should_not_duplicate() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+
+        let input = CreateMessage {
+            client_msg_id: Some("m-1".to_string()),
+            ..CreateMessage::new("hello", &[])
+        };
+        let (a, b) = tokio::join!(
+            state.create_message(input.clone(), 1, 1),
+            state.create_message(input.clone(), 1, 1)
+        );
+        let msg = a?;
+        assert_eq!(msg.id, b?.id);
+        assert_eq!(state.create_message(input.clone(), 1, 1).await?.id, msg.id);
+
+        // keys are per sender and chat
+        let other = state.create_message(input.clone(), 1, 2).await?;
+        assert_ne!(other.id, msg.id);
+        let other = state.create_message(input, 4, 1).await?;
+        assert_ne!(other.id, msg.id);
+
+        let input = CreateMessage {
+            client_msg_id: Some("x".repeat(65)),
+            ..CreateMessage::new("hello", &[])
+        };
+        let ret = state.create_message(input, 1, 1).await;
+        assert!(matches!(ret, Err(AppError::CreateMessageError(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_empty_message_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+
+        let input = CreateMessage::new("  ", &[]);
+        let ret = state.create_message(input, 1, 1).await;
+        assert!(matches!(ret, Err(AppError::CreateMessageError(_))));
+
+        let input = CreateMessage::new("hello", &[" "]);
+        let ret = state.create_message(input, 1, 1).await;
+        assert!(matches!(ret, Err(AppError::CreateMessageError(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_message_should_keep_revisions() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+        let user = state.find_user_by_email("user1@acme.org").await?.unwrap();
+
+        let input = CreateMessage::new("helo", &["/files/1/abc.png"]);
+        let msg = state.create_message(input, 1, user.id).await?;
+        assert!(msg.edited_at.is_none());
+
+        let input = UpdateMessage::new(Some("hello"), None);
+        let msg = state.update_message(input, 1, msg.id, &user).await?;
+        assert_eq!(msg.content, "hello");
+        assert_eq!(msg.images, vec!["/files/1/abc.png"]);
+        assert!(msg.edited_at.is_some());
+
+        let input = UpdateMessage::new(None, Some(&[]));
+        let msg = state.update_message(input, 1, msg.id, &user).await?;
+        assert_eq!(msg.content, "hello");
+        assert!(msg.images.is_empty());
+
+        let edits: Vec<(String, i64)> = sqlx::query_as(
+            "SELECT content, editor_id FROM message_edits WHERE message_id = $1 ORDER BY id",
+        )
+        .bind(msg.id)
+        .fetch_all(&state.pool)
+        .await?;
+        assert_eq!(
+            edits,
+            vec![
+                ("helo".to_string(), user.id),
+                ("hello".to_string(), user.id)
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_message_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+        let user1 = state.find_user_by_email("user1@acme.org").await?.unwrap();
+        let user2 = state.find_user_by_email("user2@acme.org").await?.unwrap();
+
+        let input = CreateMessage::new("hello", &[]);
+        let msg = state.create_message(input, 1, user1.id).await?;
+
+        let input = UpdateMessage::new(Some("hi"), None);
+        let ret = state.update_message(input, 1, msg.id, &user2).await;
+        assert!(matches!(ret, Err(AppError::MessageAccessDenied(_))));
+
+        let input = UpdateMessage::new(Some("hi"), None);
+        let ret = state.update_message(input, 2, msg.id, &user1).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        let input = UpdateMessage::new(Some(" "), None);
+        let ret = state.update_message(input, 1, msg.id, &user1).await;
+        assert!(matches!(ret, Err(AppError::UpdateMessageError(_))));
+
+        // the window is over once the message is older than it
+        sqlx::query("UPDATE messages SET created_at = NOW() - INTERVAL '2 minutes' WHERE id = $1")
+            .bind(msg.id)
+            .execute(&state.pool)
+            .await?;
+        state
+            .update_workspace(1, UpdateWorkspace::new(Some(60)))
+            .await?;
+        let input = UpdateMessage::new(Some("hi"), None);
+        let ret = state.update_message(input, 1, msg.id, &user1).await;
+        assert!(matches!(ret, Err(AppError::UpdateMessageError(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_message_should_leave_tombstone() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+        let user1 = state.find_user_by_email("user1@acme.org").await?.unwrap();
+        let user2 = state.find_user_by_email("user2@acme.org").await?.unwrap();
+
+        let input = CreateMessage::new("helo", &["/files/1/abc.png"]);
+        let msg = state.create_message(input, 1, user1.id).await?;
+        let input = UpdateMessage::new(Some("hello"), None);
+        state.update_message(input, 1, msg.id, &user1).await?;
+
+        let ret = state.delete_message(1, msg.id, &user2, false).await;
+        assert!(matches!(ret, Err(AppError::MessageAccessDenied(_))));
+
+        let deleted = state.delete_message(1, msg.id, &user1, false).await?;
+        assert_eq!(deleted.content, "");
+        assert!(deleted.images.is_empty());
+        assert!(deleted.deleted_at.is_some());
+
+        // stays in the list, and can not be edited anymore
+        let msgs = state.list_messages(ListMessages::default(), 1).await?;
+        assert_eq!(msgs[0].id, msg.id);
+        assert!(msgs[0].deleted_at.is_some());
+        let input = UpdateMessage::new(Some("hi"), None);
+        let ret = state.update_message(input, 1, msg.id, &user1).await;
+        assert!(matches!(ret, Err(AppError::UpdateMessageError(_))));
+
+        let edits: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM message_edits WHERE message_id = $1")
+                .bind(msg.id)
+                .fetch_one(&state.pool)
+                .await?;
+        assert_eq!(edits, 1);
+
+        // admins can retract messages of others, which is idempotent
+        let again = state.delete_message(1, msg.id, &user2, true).await?;
+        assert_eq!(again.deleted_at, deleted.deleted_at);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn expired_messages_should_be_swept() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+        let now = Utc::now();
+
+        // chat 2 keeps messages for a minute
+        let input = UpdateChat {
+            message_ttl_secs: Some(60),
+            ..Default::default()
+        };
+        state.update_chat(2, input).await?;
+        let secret = state
+            .create_message(CreateMessage::new("the password is hunter42", &[]), 2, 1)
+            .await?;
+        let expires_at = secret.expires_at.unwrap();
+        assert!(expires_at > now + Duration::seconds(59));
+        state
+            .add_reaction(2, secret.id, 2, AddReaction::new("🔥"))
+            .await?;
+        let user1 = state.find_user_by_email("user1@acme.org").await?.unwrap();
+        let input = UpdateMessage::new(Some("the password is hunter43"), None);
+        state.update_message(input, 2, secret.id, &user1).await?;
+
+        // an earlier expiry of the message wins over the chat's
+        let input = CreateMessage {
+            expires_at: Some(now + Duration::seconds(10)),
+            ..CreateMessage::new("gone soon", &[])
+        };
+        let soon = state.create_message(input, 2, 1).await?;
+        let input = CreateMessage {
+            expires_at: Some(now + Duration::hours(1)),
+            ..CreateMessage::new("capped", &[])
+        };
+        let capped = state.create_message(input, 2, 1).await?;
+        assert!(capped.expires_at.unwrap() <= expires_at + Duration::seconds(1));
+        let kept = state
+            .create_message(CreateMessage::new("kept", &[]), 1, 1)
+            .await?;
+        assert!(kept.expires_at.is_none());
+
+        assert_eq!(state.sweep_expired_messages(now).await?, 0);
+        assert_eq!(
+            state
+                .sweep_expired_messages(now + Duration::seconds(30))
+                .await?,
+            1
+        );
+        assert_eq!(
+            state
+                .sweep_expired_messages(now + Duration::seconds(120))
+                .await?,
+            2
+        );
+        assert_eq!(
+            state
+                .sweep_expired_messages(now + Duration::seconds(120))
+                .await?,
+            0
+        );
+
+        let msgs = state.list_messages(ListMessages::default(), 2).await?;
+        assert!(msgs
+            .iter()
+            .all(|m| m.content.is_empty() && m.deleted_at.is_some()));
+        let swept = msgs.iter().find(|m| m.id == secret.id).unwrap();
+        assert_eq!(swept.deleted_at, swept.expires_at);
+        assert!(swept.reactions.is_empty());
+        // the edit history outlives the content
+        let edits: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM message_edits WHERE message_id = $1")
+                .bind(secret.id)
+                .fetch_one(&state.pool)
+                .await?;
+        assert_eq!(edits, 1);
+        assert!(msgs.iter().any(|m| m.id == soon.id));
+        let msgs = state.list_messages(ListMessages::default(), 1).await?;
+        assert_eq!(msgs[0].content, "kept");
+
+        let input = CreateMessage {
+            expires_at: Some(now - Duration::seconds(1)),
+            ..CreateMessage::new("too late", &[])
+        };
+        let ret = state.create_message(input, 2, 1).await;
+        assert!(matches!(ret, Err(AppError::CreateMessageError(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn thread_replies_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+        let user = state.find_user_by_email("user1@acme.org").await?.unwrap();
+
+        let parent = state
+            .create_message(CreateMessage::new("topic", &[]), 1, user.id)
+            .await?;
+        let other = state
+            .create_message(CreateMessage::new("another topic", &[]), 1, user.id)
+            .await?;
+        let mut replies = vec![];
+        for i in 0..3 {
+            let input = CreateMessage::reply(parent.id, &format!("reply {}", i));
+            replies.push(state.create_message(input, 1, user.id).await?);
+        }
+        assert_eq!(replies[0].parent_id, Some(parent.id));
+
+        // replies are left out of the chat, and summarized on their parent
+        let msgs = state.list_messages(ListMessages::default(), 1).await?;
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(msgs[0].id, other.id);
+        assert_eq!(msgs[0].reply_count, 0);
+        assert_eq!(msgs[1].reply_count, 3);
+        assert_eq!(msgs[1].last_reply_at, Some(replies[2].created_at));
+
+        let input = ListMessages::new(None, None, Some(2));
+        let msgs = state.list_replies(input, 1, parent.id).await?;
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(msgs[0].content, "reply 2");
+        let input = ListMessages::new(Some(msgs[1].id), None, None);
+        let msgs = state.list_replies(input, 1, parent.id).await?;
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(msgs[0].content, "reply 0");
+
+        let ret = state
+            .list_replies(ListMessages::default(), 2, parent.id)
+            .await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        // threads are not nested, and live in one chat
+        let input = CreateMessage::reply(replies[0].id, "nested");
+        let ret = state.create_message(input, 1, user.id).await;
+        assert!(matches!(ret, Err(AppError::CreateMessageError(_))));
+        let input = CreateMessage::reply(parent.id, "elsewhere");
+        let ret = state.create_message(input, 2, user.id).await;
+        assert!(matches!(ret, Err(AppError::CreateMessageError(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn forward_message_should_point_to_original() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+        let user1 = state.find_user_by_email("user1@acme.org").await?.unwrap();
+
+        let input = CreateMessage::new("release plan", &["/files/1/plan.png"]);
+        let original = state.create_message(input, 2, 2).await?;
+
+        let input = ForwardMessage::new(3, "fyi");
+        let fwd = state
+            .forward_message(input, 2, original.id, user1.id)
+            .await?;
+        assert_eq!(fwd.chat_id, 3);
+        assert_eq!(fwd.content, "fyi");
+        assert!(fwd.images.is_empty());
+        assert_eq!(fwd.forwarded_from, Some(original.id));
+        let forwarded = fwd.forwarded.clone().unwrap();
+        assert_eq!(forwarded.sender_id, 2);
+        assert_eq!(forwarded.content, "release plan");
+
+        // forwarding a forward points to the original
+        let input = ForwardMessage::new(1, "");
+        let fwd2 = state.forward_message(input, 3, fwd.id, user1.id).await?;
+        assert_eq!(fwd2.forwarded_from, Some(original.id));
+
+        // the forwarder only edits the comment, edits of the original show through
+        let input = UpdateMessage::new(Some(""), None);
+        let msg = state.update_message(input, 3, fwd.id, &user1).await?;
+        assert_eq!(msg.content, "");
+        let sender = state.find_user_by_email("user2@acme.org").await?.unwrap();
+        let input = UpdateMessage::new(Some("release plan v2"), None);
+        state.update_message(input, 2, original.id, &sender).await?;
+        let msgs = state.list_messages(ListMessages::default(), 3).await?;
+        assert_eq!(
+            msgs[0].forwarded.as_ref().unwrap().content,
+            "release plan v2"
+        );
+
+        let ret = state
+            .forward_message(ForwardMessage::new(3, ""), 1, original.id, user1.id)
+            .await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn quote_should_be_in_same_chat() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+
+        let quoted = state
+            .create_message(CreateMessage::new("who owns this?", &[]), 1, 2)
+            .await?;
+        let input = CreateMessage {
+            quoted_id: Some(quoted.id),
+            ..CreateMessage::reply(quoted.id, "me")
+        };
+        let msg = state.create_message(input, 1, 1).await?;
+        assert_eq!(msg.quoted_id, Some(quoted.id));
+
+        let replies = state
+            .list_replies(ListMessages::default(), 1, quoted.id)
+            .await?;
+        assert_eq!(replies[0].quote.as_ref().unwrap().content, "who owns this?");
+        assert!(replies[0].forwarded.is_none());
+
+        let input = CreateMessage {
+            quoted_id: Some(quoted.id),
+            ..CreateMessage::new("elsewhere", &[])
+        };
+        let ret = state.create_message(input, 3, 1).await;
+        assert!(matches!(ret, Err(AppError::CreateMessageError(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_messages_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+
+        let mut ids = Vec::new();
+        for i in 0..10 {
+            let input = CreateMessage::new(&format!("message {}", i), &[]);
+            ids.push(state.create_message(input, 1, 1).await?.id);
+        }
+
+        let input = ListMessages::new(None, None, Some(4));
+        let msgs = state.list_messages(input, 1).await?;
+        assert_eq!(msgs.len(), 4);
+        assert_eq!(msgs[0].id, ids[9]);
+        assert_eq!(msgs[3].id, ids[6]);
+
+        let input = ListMessages::new(Some(msgs[3].id), None, Some(4));
+        let msgs = state.list_messages(input, 1).await?;
+        assert_eq!(msgs.len(), 4);
+        assert_eq!(msgs[0].id, ids[5]);
+        assert_eq!(msgs[3].id, ids[2]);
+
+        let input = ListMessages::new(None, Some(ids[2]), Some(3));
+        let msgs = state.list_messages(input, 1).await?;
+        assert_eq!(msgs.len(), 3);
+        assert_eq!(msgs[0].id, ids[5]);
+        assert_eq!(msgs[2].id, ids[3]);
+
+        let input = ListMessages::new(Some(ids[5]), Some(ids[2]), None);
+        let msgs = state.list_messages(input, 1).await?;
+        assert_eq!(msgs.len(), 2);
+
+        let input = ListMessages::new(None, None, Some(1000));
+        let msgs = state.list_messages(input, 1).await?;
+        assert_eq!(msgs.len(), 10);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_messages_with_invalid_params_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+
+        let input = ListMessages::new(None, None, Some(0));
+        let ret = state.list_messages(input, 1).await;
+        assert!(matches!(ret, Err(AppError::ListMessagesError(_))));
+
+        let first = state
+            .create_message(CreateMessage::new("first", &[]), 1, 1)
+            .await?;
+        let second = state
+            .create_message(CreateMessage::new("second", &[]), 1, 1)
+            .await?;
+        let input = ListMessages::new(Some(first.id), Some(second.id), None);
+        let ret = state.list_messages(input, 1).await;
+        assert!(matches!(ret, Err(AppError::ListMessagesError(_))));
+
+        // cursors must be messages of the chat
+        let input = ListMessages::new(Some(999), None, None);
+        let ret = state.list_messages(input, 1).await;
+        assert!(matches!(ret, Err(AppError::ListMessagesError(_))));
+        let input = ListMessages::new(None, Some(first.id), None);
+        let ret = state.list_messages(input, 2).await;
+        assert!(matches!(ret, Err(AppError::ListMessagesError(_))));
+
+        Ok(())
+    }
+}
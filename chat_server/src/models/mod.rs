@@ -1,56 +1,25 @@
 mod chat;
+mod chat_member;
+mod mention;
+mod message;
+mod pin;
+mod poll;
+mod reaction;
+mod reminder;
+mod scheduled;
+mod search;
 mod user;
 mod workspace;
 
 pub use chat::{CreateChat, UpdateChat};
+pub use chat_member::{AddChatMembers, ReadChat};
+pub use mention::ListMentions;
+pub(crate) use message::check_client_msg_id;
+pub use message::{CreateMessage, ForwardMessage, ListMessages, UpdateMessage};
+pub use pin::PinMessage;
+pub use poll::VotePoll;
+pub use reaction::AddReaction;
+pub use scheduled::{ScheduleMessage, UpdateScheduledMessage};
+pub use search::SearchMessages;
 pub use user::{CreateUser, SigninUser};
-
-use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
-
-#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
-pub struct User {
-    pub id: i64,
-    pub ws_id: i64,
-    pub fullname: String,
-    pub email: String,
-    #[sqlx(default)]
-    #[serde(skip)]
-    pub password_hash: Option<String>,
-    pub created_at: DateTime<Utc>,
-}
-
-#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
-pub struct Workspace {
-    pub id: i64,
-    pub name: String,
-    pub owner_id: i64,
-    pub created_at: DateTime<Utc>,
-}
-
-#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
-pub struct ChatUser {
-    pub id: i64,
-    pub fullname: String,
-    pub email: String,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, PartialOrd, sqlx::Type)]
-#[sqlx(type_name = "chat_type", rename_all = "snake_case")]
-pub enum ChatType {
-    Single,
-    Group,
-    PrivateChannel,
-    PublicChannel,
-}
-
-#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
-pub struct Chat {
-    pub id: i64,
-    pub ws_id: i64,
-    pub name: Option<String>,
-    pub r#type: ChatType,
-    pub members: Vec<i64>,
-    pub created_at: DateTime<Utc>,
-}
+pub use workspace::UpdateWorkspace;
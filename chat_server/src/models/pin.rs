@@ -0,0 +1,226 @@
+use chat_core::Pin;
+use serde::{Deserialize, Serialize};
+
+use crate::{error::AppError, AppState};
+
+use super::message::MESSAGE_FIELDS;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PinMessage {
+    pub message_id: i64,
+}
+
+impl AppState {
+    /// Pin a message of a chat, up to the chat's `max_pins`. Pinning a pinned message
+    /// keeps the original pin.
+    pub async fn pin_message(
+        &self,
+        chat_id: i64,
+        user_id: i64,
+        input: PinMessage,
+    ) -> Result<Pin, AppError> {
+        let mut tx = self.pool.begin().await?;
+        // serializes pins of the chat, so the limit holds
+        let max_pins: Option<i32> =
+            sqlx::query_scalar("SELECT max_pins FROM chats WHERE id = $1 FOR UPDATE")
+                .bind(chat_id)
+                .fetch_optional(&mut *tx)
+                .await?;
+        let Some(max_pins) = max_pins else {
+            return Err(AppError::NotFound(format!(
+                "Chat with id={} not exist.",
+                chat_id
+            )));
+        };
+
+        let deleted: Option<bool> = sqlx::query_scalar(
+            "SELECT deleted_at IS NOT NULL FROM messages WHERE id = $1 AND chat_id = $2",
+        )
+        .bind(input.message_id)
+        .bind(chat_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        match deleted {
+            None => {
+                return Err(AppError::NotFound(format!(
+                    "Message with id={} not exist.",
+                    input.message_id
+                )))
+            }
+            Some(true) => {
+                return Err(AppError::PinError(format!(
+                    "Message {} has been deleted.",
+                    input.message_id
+                )))
+            }
+            Some(false) => {}
+        }
+
+        let (count, pinned): (i64, bool) = sqlx::query_as(
+            "SELECT COUNT(*), COALESCE(bool_or(message_id = $2), FALSE) FROM chat_pins WHERE chat_id = $1",
+        )
+        .bind(chat_id)
+        .bind(input.message_id)
+        .fetch_one(&mut *tx)
+        .await?;
+        if !pinned {
+            if count >= max_pins as i64 {
+                return Err(AppError::PinError(format!(
+                    "Chat {} can have at most {} pins.",
+                    chat_id, max_pins
+                )));
+            }
+
+            sqlx::query(
+                r#"
+                INSERT INTO chat_pins (chat_id, message_id, pinned_by)
+                VALUES ($1, $2, $3)
+                "#,
+            )
+            .bind(chat_id)
+            .bind(input.message_id)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        let pin = sqlx::query_as(&format!(
+            r#"
+            SELECT p.pinned_by, p.pinned_at, m.*
+            FROM chat_pins p
+            JOIN (SELECT {MESSAGE_FIELDS} FROM messages) m ON m.id = p.message_id
+            WHERE p.chat_id = $1 AND p.message_id = $2
+            "#,
+        ))
+        .bind(chat_id)
+        .bind(input.message_id)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(pin)
+    }
+
+    pub async fn unpin_message(&self, chat_id: i64, message_id: i64) -> Result<(), AppError> {
+        let ret = sqlx::query("DELETE FROM chat_pins WHERE chat_id = $1 AND message_id = $2")
+            .bind(chat_id)
+            .bind(message_id)
+            .execute(&self.pool)
+            .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!(
+                "Message {} is not pinned in chat {}.",
+                message_id, chat_id
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Pinned messages of a chat, latest pin first.
+    pub async fn list_pins(&self, chat_id: i64) -> Result<Vec<Pin>, AppError> {
+        let pins = sqlx::query_as(&format!(
+            r#"
+            SELECT p.pinned_by, p.pinned_at, m.*
+            FROM chat_pins p
+            JOIN (SELECT {MESSAGE_FIELDS} FROM messages) m ON m.id = p.message_id
+            WHERE p.chat_id = $1
+            ORDER BY p.pinned_at DESC, p.message_id DESC
+            "#,
+        ))
+        .bind(chat_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(pins)
+    }
+}
+
+#[cfg(test)]
+impl PinMessage {
+    pub fn new(message_id: i64) -> Self {
+        Self { message_id }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{Ok, Result};
+
+    use crate::{
+        models::{CreateMessage, UpdateChat},
+        AppConfig,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn pins_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+        let mut ids = vec![];
+        for i in 0..3 {
+            let input = CreateMessage::new(&format!("runbook {}", i), &[]);
+            ids.push(state.create_message(input, 1, 1).await?.id);
+        }
+
+        let pin = state.pin_message(1, 2, PinMessage::new(ids[0])).await?;
+        assert_eq!(pin.pinned_by, 2);
+        assert_eq!(pin.message.id, ids[0]);
+        // pinned again by someone else, the first pin stays
+        let pin = state.pin_message(1, 3, PinMessage::new(ids[0])).await?;
+        assert_eq!(pin.pinned_by, 2);
+        state.pin_message(1, 3, PinMessage::new(ids[1])).await?;
+
+        let pins = state.list_pins(1).await?;
+        let pinned: Vec<_> = pins.iter().map(|p| p.message.id).collect();
+        assert_eq!(pinned, vec![ids[1], ids[0]]);
+
+        state.unpin_message(1, ids[1]).await?;
+        assert_eq!(state.list_pins(1).await?.len(), 1);
+        let ret = state.unpin_message(1, ids[1]).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pins_should_be_limited() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+        let chat = state
+            .update_chat(
+                1,
+                UpdateChat {
+                    max_pins: Some(1),
+                    ..Default::default()
+                },
+            )
+            .await?;
+        assert_eq!(chat.max_pins, 1);
+        let ret = state
+            .update_chat(
+                1,
+                UpdateChat {
+                    max_pins: Some(0),
+                    ..Default::default()
+                },
+            )
+            .await;
+        assert!(matches!(ret, Err(AppError::UpdateChatError(_))));
+
+        let msg1 = state
+            .create_message(CreateMessage::new("a", &[]), 1, 1)
+            .await?;
+        let msg2 = state
+            .create_message(CreateMessage::new("b", &[]), 1, 1)
+            .await?;
+        state.pin_message(1, 1, PinMessage::new(msg1.id)).await?;
+        let ret = state.pin_message(1, 1, PinMessage::new(msg2.id)).await;
+        assert!(matches!(ret, Err(AppError::PinError(_))));
+
+        // messages of other chats can not be pinned
+        let ret = state.pin_message(2, 1, PinMessage::new(msg2.id)).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,341 @@
+use chat_core::Poll;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{types::Json, FromRow, Postgres, Transaction};
+
+use crate::{error::AppError, AppState};
+
+const MIN_POLL_OPTIONS: usize = 2;
+const MAX_POLL_OPTIONS: usize = 10;
+const MAX_POLL_OPTION_LEN: usize = 256;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CreatePoll {
+    pub options: Vec<String>,
+    #[serde(default)]
+    pub multi_choice: bool,
+    #[serde(default)]
+    pub anonymous: bool,
+    #[serde(default)]
+    pub closes_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VotePoll {
+    // replaces the user's votes, empty to retract them
+    pub option_ids: Vec<i64>,
+}
+
+impl AppState {
+    /// Vote on a poll until it closes, and return its results.
+    pub async fn vote_poll(
+        &self,
+        chat_id: i64,
+        id: i64,
+        user_id: i64,
+        input: VotePoll,
+    ) -> Result<Poll, AppError> {
+        let mut option_ids = input.option_ids;
+        option_ids.sort_unstable();
+        option_ids.dedup();
+
+        let mut tx = self.pool.begin().await?;
+        // serializes votes on the poll, so each change notifies the results once
+        let poll: Option<VotablePoll> = sqlx::query_as(
+            r#"
+            SELECT p.multi_choice, p.closes_at, m.deleted_at
+            FROM polls p
+            JOIN messages m ON m.id = p.message_id
+            WHERE p.message_id = $1 AND m.chat_id = $2
+            FOR UPDATE OF p
+            "#,
+        )
+        .bind(id)
+        .bind(chat_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(VotablePoll {
+            multi_choice,
+            closes_at,
+            deleted_at,
+        }) = poll
+        else {
+            return Err(AppError::NotFound(format!(
+                "Poll with id={} not exist.",
+                id
+            )));
+        };
+        if deleted_at.is_some() {
+            return Err(AppError::PollError(format!(
+                "Message {} has been deleted.",
+                id
+            )));
+        }
+        if closes_at.is_some_and(|t| t <= Utc::now()) {
+            return Err(AppError::PollError(format!("Poll {} is closed.", id)));
+        }
+        if !multi_choice && option_ids.len() > 1 {
+            return Err(AppError::PollError(format!(
+                "Poll {} allows a single choice.",
+                id
+            )));
+        }
+
+        let valid: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM poll_options WHERE message_id = $1 AND id = ANY($2)",
+        )
+        .bind(id)
+        .bind(&option_ids)
+        .fetch_one(&mut *tx)
+        .await?;
+        if valid as usize != option_ids.len() {
+            return Err(AppError::PollError(format!(
+                "Options {:?} are not all options of poll {}.",
+                option_ids, id
+            )));
+        }
+
+        sqlx::query("DELETE FROM poll_votes WHERE message_id = $1 AND user_id = $2")
+            .bind(id)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            r#"
+            INSERT INTO poll_votes (message_id, user_id, option_id)
+            SELECT $1, $2, * FROM unnest($3::BIGINT[])
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(&option_ids)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("UPDATE polls SET version = version + 1 WHERE message_id = $1")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        let poll = fetch_poll(&mut tx, id).await?;
+        tx.commit().await?;
+
+        poll.ok_or_else(|| AppError::NotFound(format!("Poll with id={} not exist.", id)))
+    }
+}
+
+#[derive(Debug, FromRow)]
+struct VotablePoll {
+    multi_choice: bool,
+    closes_at: Option<DateTime<Utc>>,
+    deleted_at: Option<DateTime<Utc>>,
+}
+
+/// Store the poll of a new message.
+pub(super) async fn save_poll(
+    tx: &mut Transaction<'_, Postgres>,
+    message_id: i64,
+    poll: &CreatePoll,
+) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+        INSERT INTO polls (message_id, multi_choice, anonymous, closes_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+    )
+    .bind(message_id)
+    .bind(poll.multi_choice)
+    .bind(poll.anonymous)
+    .bind(poll.closes_at)
+    .execute(&mut **tx)
+    .await?;
+
+    let texts: Vec<&str> = poll.options.iter().map(|s| s.trim()).collect();
+    sqlx::query(
+        r#"
+        INSERT INTO poll_options (message_id, position, text)
+        SELECT $1, t.position - 1, t.text FROM unnest($2::TEXT[]) WITH ORDINALITY t(text, position)
+        "#,
+    )
+    .bind(message_id)
+    .bind(texts)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+/// Options and results of a poll, none when the message is not a poll.
+pub(super) async fn fetch_poll(
+    tx: &mut Transaction<'_, Postgres>,
+    message_id: i64,
+) -> Result<Option<Poll>, AppError> {
+    let poll: Option<Json<Poll>> = sqlx::query_scalar("SELECT poll_json($1)")
+        .bind(message_id)
+        .fetch_one(&mut **tx)
+        .await?;
+
+    Ok(poll.map(|Json(poll)| poll))
+}
+
+pub(super) fn validate_poll(poll: &CreatePoll) -> Result<(), String> {
+    if !(MIN_POLL_OPTIONS..=MAX_POLL_OPTIONS).contains(&poll.options.len()) {
+        return Err(format!(
+            "Poll must have {} to {} options.",
+            MIN_POLL_OPTIONS, MAX_POLL_OPTIONS
+        ));
+    }
+    let mut texts: Vec<&str> = poll.options.iter().map(|s| s.trim()).collect();
+    if texts
+        .iter()
+        .any(|s| s.is_empty() || s.chars().count() > MAX_POLL_OPTION_LEN)
+    {
+        return Err(format!(
+            "Poll option must have 1 to {} characters.",
+            MAX_POLL_OPTION_LEN
+        ));
+    }
+    texts.sort_unstable();
+    texts.dedup();
+    if texts.len() != poll.options.len() {
+        return Err("Poll options must be different.".to_string());
+    }
+    if poll.closes_at.is_some_and(|t| t <= Utc::now()) {
+        return Err("Poll must close in the future.".to_string());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+impl CreatePoll {
+    pub fn new(options: &[&str], multi_choice: bool, anonymous: bool) -> Self {
+        Self {
+            options: options.iter().map(|s| s.to_string()).collect(),
+            multi_choice,
+            anonymous,
+            closes_at: None,
+        }
+    }
+}
+
+#[cfg(test)]
+impl VotePoll {
+    pub fn new(option_ids: &[i64]) -> Self {
+        Self {
+            option_ids: option_ids.to_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{Ok, Result};
+
+    use crate::{
+        models::{CreateMessage, ListMessages},
+        AppConfig,
+    };
+
+    use super::*;
+
+    fn poll_message(question: &str, poll: CreatePoll) -> CreateMessage {
+        CreateMessage {
+            poll: Some(poll),
+            ..CreateMessage::new(question, &[])
+        }
+    }
+
+    #[tokio::test]
+    async fn poll_votes_should_be_counted() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+
+        let input = poll_message("lunch?", CreatePoll::new(&["pizza", "sushi"], false, false));
+        let msg = state.create_message(input, 1, 1).await?;
+        let poll = msg.poll.unwrap();
+        assert_eq!(poll.options.len(), 2);
+        assert_eq!(poll.options[0].text, "pizza");
+        let (pizza, sushi) = (poll.options[0].id, poll.options[1].id);
+
+        state
+            .vote_poll(1, msg.id, 2, VotePoll::new(&[pizza]))
+            .await?;
+        state
+            .vote_poll(1, msg.id, 3, VotePoll::new(&[pizza]))
+            .await?;
+        // voting again replaces the vote
+        let poll = state
+            .vote_poll(1, msg.id, 3, VotePoll::new(&[sushi]))
+            .await?;
+        assert_eq!(poll.voter_count, 2);
+        assert_eq!(poll.options[0].voters, vec![2]);
+        assert_eq!(poll.options[1].votes, 1);
+
+        let ret = state
+            .vote_poll(1, msg.id, 2, VotePoll::new(&[pizza, sushi]))
+            .await;
+        assert!(matches!(ret, Err(AppError::PollError(_))));
+        let ret = state.vote_poll(1, msg.id, 2, VotePoll::new(&[999])).await;
+        assert!(matches!(ret, Err(AppError::PollError(_))));
+
+        // results come with the message
+        let msgs = state.list_messages(ListMessages::default(), 1).await?;
+        assert_eq!(msgs[0].poll.as_ref().unwrap().voter_count, 2);
+
+        let poll = state.vote_poll(1, msg.id, 2, VotePoll::new(&[])).await?;
+        assert_eq!(poll.voter_count, 1);
+        assert_eq!(poll.options[0].votes, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn anonymous_multi_choice_poll_should_hide_voters() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+
+        let input = poll_message("days?", CreatePoll::new(&["mon", "tue", "wed"], true, true));
+        let msg = state.create_message(input, 1, 1).await?;
+        let ids: Vec<i64> = msg.poll.unwrap().options.iter().map(|o| o.id).collect();
+
+        let poll = state
+            .vote_poll(1, msg.id, 2, VotePoll::new(&[ids[0], ids[2]]))
+            .await?;
+        assert_eq!(poll.voter_count, 1);
+        assert_eq!(poll.options[0].votes, 1);
+        assert_eq!(poll.options[2].votes, 1);
+        assert!(poll.options.iter().all(|o| o.voters.is_empty()));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn closed_or_invalid_poll_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+
+        let input = poll_message("one?", CreatePoll::new(&["yes"], false, false));
+        let ret = state.create_message(input, 1, 1).await;
+        assert!(matches!(ret, Err(AppError::CreateMessageError(_))));
+        let input = poll_message("same?", CreatePoll::new(&["a", " a "], false, false));
+        let ret = state.create_message(input, 1, 1).await;
+        assert!(matches!(ret, Err(AppError::CreateMessageError(_))));
+
+        let input = poll_message("ok?", CreatePoll::new(&["yes", "no"], false, false));
+        let msg = state.create_message(input, 1, 1).await?;
+        let option = msg.poll.unwrap().options[0].id;
+        sqlx::query("UPDATE polls SET closes_at = NOW() WHERE message_id = $1")
+            .bind(msg.id)
+            .execute(&state.pool)
+            .await?;
+        let ret = state
+            .vote_poll(1, msg.id, 2, VotePoll::new(&[option]))
+            .await;
+        assert!(matches!(ret, Err(AppError::PollError(_))));
+
+        let plain = state
+            .create_message(CreateMessage::new("no poll", &[]), 1, 1)
+            .await?;
+        assert!(plain.poll.is_none());
+        let ret = state.vote_poll(1, plain.id, 2, VotePoll::new(&[])).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        Ok(())
+    }
+}
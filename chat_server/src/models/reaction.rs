@@ -0,0 +1,194 @@
+use chat_core::Reaction;
+use serde::{Deserialize, Serialize};
+
+use crate::{error::AppError, AppState};
+
+const MAX_EMOJI_LEN: usize = 64;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AddReaction {
+    pub emoji: String,
+}
+
+impl AppState {
+    /// React to a message with an emoji, reacting twice with the same one is a no-op.
+    /// Returns the reactions of the message afterwards.
+    pub async fn add_reaction(
+        &self,
+        chat_id: i64,
+        id: i64,
+        user_id: i64,
+        input: AddReaction,
+    ) -> Result<Vec<Reaction>, AppError> {
+        let emoji = input.emoji.trim();
+        if emoji.is_empty() || emoji.chars().count() > MAX_EMOJI_LEN {
+            return Err(AppError::ReactionError(format!(
+                "Emoji must have 1 to {} characters.",
+                MAX_EMOJI_LEN
+            )));
+        }
+
+        let deleted: Option<bool> = sqlx::query_scalar(
+            "SELECT deleted_at IS NOT NULL FROM messages WHERE id = $1 AND chat_id = $2",
+        )
+        .bind(id)
+        .bind(chat_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        match deleted {
+            None => {
+                return Err(AppError::NotFound(format!(
+                    "Message with id={} not exist.",
+                    id
+                )))
+            }
+            Some(true) => {
+                return Err(AppError::ReactionError(format!(
+                    "Message {} has been deleted.",
+                    id
+                )))
+            }
+            Some(false) => {}
+        }
+
+        sqlx::query(
+            r#"
+            INSERT INTO message_reactions (message_id, user_id, emoji)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(emoji)
+        .execute(&self.pool)
+        .await?;
+
+        self.fetch_reactions(id).await
+    }
+
+    /// Take back a reaction of the user, returns the reactions of the message afterwards.
+    pub async fn remove_reaction(
+        &self,
+        chat_id: i64,
+        id: i64,
+        user_id: i64,
+        emoji: &str,
+    ) -> Result<Vec<Reaction>, AppError> {
+        let ret = sqlx::query(
+            r#"
+            DELETE FROM message_reactions r
+            USING messages m
+            WHERE m.id = r.message_id AND m.chat_id = $1
+                AND r.message_id = $2 AND r.user_id = $3 AND r.emoji = $4
+            "#,
+        )
+        .bind(chat_id)
+        .bind(id)
+        .bind(user_id)
+        .bind(emoji)
+        .execute(&self.pool)
+        .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!(
+                "Reaction {} of user {} on message {} not exist.",
+                emoji, user_id, id
+            )));
+        }
+
+        self.fetch_reactions(id).await
+    }
+
+    async fn fetch_reactions(&self, id: i64) -> Result<Vec<Reaction>, AppError> {
+        let reactions = sqlx::query_as(
+            r#"
+            SELECT emoji, array_agg(user_id ORDER BY created_at, user_id) AS user_ids
+            FROM message_reactions
+            WHERE message_id = $1
+            GROUP BY emoji
+            ORDER BY MIN(created_at), emoji
+            "#,
+        )
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(reactions)
+    }
+}
+
+#[cfg(test)]
+impl AddReaction {
+    pub fn new(emoji: &str) -> Self {
+        Self {
+            emoji: emoji.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{Ok, Result};
+
+    use crate::{
+        models::{CreateMessage, ListMessages},
+        AppConfig,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn reactions_should_be_grouped_by_emoji() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+        let msg = state
+            .create_message(CreateMessage::new("hello", &[]), 1, 1)
+            .await?;
+
+        state
+            .add_reaction(1, msg.id, 2, AddReaction::new("👍"))
+            .await?;
+        state
+            .add_reaction(1, msg.id, 3, AddReaction::new("🎉"))
+            .await?;
+        state
+            .add_reaction(1, msg.id, 1, AddReaction::new("👍"))
+            .await?;
+        let reactions = state
+            .add_reaction(1, msg.id, 1, AddReaction::new("👍"))
+            .await?;
+        assert_eq!(reactions.len(), 2);
+        assert_eq!(reactions[0].emoji, "👍");
+        assert_eq!(reactions[0].user_ids, vec![2, 1]);
+        assert_eq!(reactions[1].user_ids, vec![3]);
+
+        let msgs = state.list_messages(ListMessages::default(), 1).await?;
+        assert_eq!(msgs[0].reactions, reactions);
+
+        let reactions = state.remove_reaction(1, msg.id, 3, "🎉").await?;
+        assert_eq!(reactions.len(), 1);
+        let ret = state.remove_reaction(1, msg.id, 3, "🎉").await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn add_reaction_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+        let msg = state
+            .create_message(CreateMessage::new("hello", &[]), 1, 1)
+            .await?;
+
+        let ret = state
+            .add_reaction(1, msg.id, 1, AddReaction::new(" "))
+            .await;
+        assert!(matches!(ret, Err(AppError::ReactionError(_))));
+
+        let ret = state
+            .add_reaction(2, msg.id, 1, AddReaction::new("👍"))
+            .await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,107 @@
+use chrono::{DateTime, Utc};
+
+use crate::{error::AppError, AppState};
+
+// due reminders sent in one run of the dispatcher
+const DISPATCH_BATCH_SIZE: i64 = 100;
+
+impl AppState {
+    /// Create a reminder, and return its id and due time. A retry with the same client
+    /// message id returns the reminder created first.
+    pub async fn create_reminder(
+        &self,
+        user_id: i64,
+        chat_id: i64,
+        text: &str,
+        remind_at: DateTime<Utc>,
+        client_msg_id: Option<&str>,
+    ) -> Result<(i64, DateTime<Utc>), AppError> {
+        // the no-op update returns the existing row
+        let reminder = sqlx::query_as(
+            r#"
+            INSERT INTO reminders (user_id, chat_id, text, remind_at, client_msg_id)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (user_id, chat_id, client_msg_id)
+            DO UPDATE SET client_msg_id = EXCLUDED.client_msg_id
+            RETURNING id, remind_at
+            "#,
+        )
+        .bind(user_id)
+        .bind(chat_id)
+        .bind(text)
+        .bind(remind_at)
+        .bind(client_msg_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(reminder)
+    }
+
+    /// Mark reminders due at `now` as sent, and return how many were sent. The notify
+    /// trigger sends each to its user, marking skips rows other dispatchers hold.
+    pub async fn dispatch_reminders(&self, now: DateTime<Utc>) -> Result<usize, AppError> {
+        let ret = sqlx::query(
+            r#"
+            UPDATE reminders SET sent_at = $1
+            WHERE id IN (
+                SELECT id FROM reminders
+                WHERE sent_at IS NULL AND remind_at <= $1
+                ORDER BY remind_at
+                LIMIT $2
+                FOR UPDATE SKIP LOCKED
+            )
+            "#,
+        )
+        .bind(now)
+        .bind(DISPATCH_BATCH_SIZE)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(ret.rows_affected() as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{Ok, Result};
+    use chrono::Duration;
+
+    use crate::AppConfig;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn reminders_should_be_sent_once_due() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+        let now = Utc::now();
+
+        let (id, _) = state
+            .create_reminder(1, 1, "stand up", now + Duration::minutes(10), Some("r-1"))
+            .await?;
+        // a retry keeps the first reminder
+        let retried = state
+            .create_reminder(1, 1, "stand up", now + Duration::minutes(11), Some("r-1"))
+            .await?;
+        assert_eq!(retried.0, id);
+        state
+            .create_reminder(1, 1, "go home", now + Duration::hours(8), None)
+            .await?;
+
+        assert_eq!(state.dispatch_reminders(now).await?, 0);
+        assert_eq!(
+            state
+                .dispatch_reminders(now + Duration::minutes(10))
+                .await?,
+            1
+        );
+        assert_eq!(
+            state
+                .dispatch_reminders(now + Duration::minutes(10))
+                .await?,
+            0
+        );
+        assert_eq!(state.dispatch_reminders(now + Duration::days(1)).await?, 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,522 @@
+use chat_core::ScheduledMessage;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Postgres, Transaction};
+
+use crate::{
+    commands::{parse_command, unescape_command},
+    error::AppError,
+    policy::ChatAction,
+    AppState,
+};
+
+use super::{
+    message::{check_new_message, insert_message},
+    CreateMessage,
+};
+
+// due messages delivered in one run of the dispatcher
+const DISPATCH_BATCH_SIZE: usize = 100;
+
+const SCHEDULED_FIELDS: &str = "id, chat_id, sender_id, content, images, parent_id, quoted_id, \
+    send_at, message_id, error, created_at";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScheduleMessage {
+    #[serde(flatten)]
+    pub message: CreateMessage,
+    pub send_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateScheduledMessage {
+    pub content: Option<String>,
+    pub images: Option<Vec<String>>,
+    pub send_at: Option<DateTime<Utc>>,
+}
+
+impl AppState {
+    /// Schedule a message to be sent at `send_at`. Slash commands, polls, client
+    /// message ids and expiry can not be scheduled, content starting with `//` is sent
+    /// with a single slash.
+    pub async fn schedule_message(
+        &self,
+        mut input: ScheduleMessage,
+        chat_id: i64,
+        user_id: i64,
+    ) -> Result<ScheduledMessage, AppError> {
+        check_send_at(input.send_at)?;
+        check_schedulable(&input.message)?;
+        unescape_command(&mut input.message.content);
+
+        let mut tx = self.pool.begin().await?;
+        check_new_message(&mut tx, &input.message, chat_id).await?;
+
+        let scheduled = sqlx::query_as(&format!(
+            r#"
+            INSERT INTO scheduled_messages
+                (chat_id, sender_id, content, images, parent_id, quoted_id, send_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING {SCHEDULED_FIELDS}
+            "#,
+        ))
+        .bind(chat_id)
+        .bind(user_id)
+        .bind(input.message.content)
+        .bind(input.message.images)
+        .bind(input.message.parent_id)
+        .bind(input.message.quoted_id)
+        .bind(input.send_at)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(scheduled)
+    }
+
+    /// Scheduled messages of a user which are not delivered yet, the next due first.
+    pub async fn list_scheduled_messages(
+        &self,
+        user_id: i64,
+    ) -> Result<Vec<ScheduledMessage>, AppError> {
+        let scheduled = sqlx::query_as(&format!(
+            r#"
+            SELECT {SCHEDULED_FIELDS}
+            FROM scheduled_messages
+            WHERE sender_id = $1 AND message_id IS NULL
+            ORDER BY send_at, id
+            "#,
+        ))
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(scheduled)
+    }
+
+    /// Edit a scheduled message which is not delivered yet. A message which failed to
+    /// be delivered is retried after the edit.
+    pub async fn update_scheduled_message(
+        &self,
+        input: UpdateScheduledMessage,
+        id: i64,
+        user_id: i64,
+    ) -> Result<ScheduledMessage, AppError> {
+        if let Some(send_at) = input.send_at {
+            check_send_at(send_at)?;
+        }
+        let content = match input.content {
+            Some(mut content) => {
+                check_not_command(&content)?;
+                unescape_command(&mut content);
+                Some(content)
+            }
+            None => None,
+        };
+
+        let mut tx = self.pool.begin().await?;
+        let current = lock_pending(&mut tx, id, user_id).await?;
+        let message = CreateMessage {
+            content: content.unwrap_or(current.content),
+            images: input.images.unwrap_or(current.images),
+            parent_id: current.parent_id,
+            quoted_id: current.quoted_id,
+            ..Default::default()
+        };
+        check_new_message(&mut tx, &message, current.chat_id)
+            .await
+            .map_err(|e| match e {
+                AppError::CreateMessageError(e) => AppError::ScheduleMessageError(e),
+                e => e,
+            })?;
+
+        let scheduled = sqlx::query_as(&format!(
+            r#"
+            UPDATE scheduled_messages
+            SET content = $2, images = $3, send_at = $4, error = NULL
+            WHERE id = $1
+            RETURNING {SCHEDULED_FIELDS}
+            "#,
+        ))
+        .bind(id)
+        .bind(message.content)
+        .bind(message.images)
+        .bind(input.send_at.unwrap_or(current.send_at))
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(scheduled)
+    }
+
+    pub async fn cancel_scheduled_message(&self, id: i64, user_id: i64) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
+        lock_pending(&mut tx, id, user_id).await?;
+        sqlx::query("DELETE FROM scheduled_messages WHERE id = $1")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Deliver due scheduled messages through the normal send path, and return how many
+    /// were handled. Each one is locked, sent and marked in a single transaction, so
+    /// concurrent dispatchers skip it and it is never sent twice. A message which can
+    /// not be sent, e.g. the sender left the chat or the database rejects it, keeps the
+    /// reason and is not retried until it is edited.
+    pub async fn dispatch_scheduled_messages(&self) -> Result<usize, AppError> {
+        let mut handled = 0;
+        while handled < DISPATCH_BATCH_SIZE {
+            let mut tx = self.pool.begin().await?;
+            let due: Option<ScheduledMessage> = sqlx::query_as(&format!(
+                r#"
+                SELECT {SCHEDULED_FIELDS}
+                FROM scheduled_messages
+                WHERE message_id IS NULL AND error IS NULL AND send_at <= NOW()
+                ORDER BY send_at, id
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+                "#,
+            ))
+            .fetch_optional(&mut *tx)
+            .await?;
+            let Some(due) = due else {
+                break;
+            };
+
+            match send_scheduled(&mut tx, &due).await {
+                Ok(()) => tx.commit().await?,
+                // the transaction is aborted, the message is marked after it so it does
+                // not block the ones behind it, unless it was sent meanwhile
+                Err(AppError::SqlxError(e)) => {
+                    drop(tx);
+                    sqlx::query(
+                        r#"
+                        UPDATE scheduled_messages SET error = $2
+                        WHERE id = $1 AND message_id IS NULL
+                        "#,
+                    )
+                    .bind(due.id)
+                    .bind(e.to_string())
+                    .execute(&self.pool)
+                    .await?;
+                }
+                Err(e) => {
+                    sqlx::query("UPDATE scheduled_messages SET error = $2 WHERE id = $1")
+                        .bind(due.id)
+                        .bind(e.to_string())
+                        .execute(&mut *tx)
+                        .await?;
+                    tx.commit().await?;
+                }
+            }
+            handled += 1;
+        }
+
+        Ok(handled)
+    }
+}
+
+// the sender must still be able to post the message when it is due, as a member of
+// the chat like `ChatAction::Write` requires
+async fn send_scheduled(
+    tx: &mut Transaction<'_, Postgres>,
+    due: &ScheduledMessage,
+) -> Result<(), AppError> {
+    let member: bool = sqlx::query_scalar(
+        r#"
+        SELECT EXISTS (
+            SELECT 1
+            FROM chat_members cm
+            JOIN chats c ON c.id = cm.chat_id
+            JOIN users u ON u.id = cm.user_id
+            WHERE cm.chat_id = $1 AND cm.user_id = $2 AND c.ws_id = u.ws_id
+        )
+        "#,
+    )
+    .bind(due.chat_id)
+    .bind(due.sender_id)
+    .fetch_one(&mut **tx)
+    .await?;
+    if !member {
+        return Err(AppError::ChatAccessDenied(format!(
+            "User {} can not {:?} chat {}.",
+            due.sender_id,
+            ChatAction::Write,
+            due.chat_id
+        )));
+    }
+
+    let message = CreateMessage {
+        content: due.content.clone(),
+        images: due.images.clone(),
+        parent_id: due.parent_id,
+        quoted_id: due.quoted_id,
+        ..Default::default()
+    };
+    check_new_message(tx, &message, due.chat_id).await?;
+    let message = insert_message(tx, message, due.chat_id, due.sender_id).await?;
+    sqlx::query("UPDATE scheduled_messages SET message_id = $2 WHERE id = $1")
+        .bind(due.id)
+        .bind(message.id)
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(())
+}
+
+// waits for a dispatcher delivering the message, which then is no longer pending
+async fn lock_pending(
+    tx: &mut Transaction<'_, Postgres>,
+    id: i64,
+    user_id: i64,
+) -> Result<ScheduledMessage, AppError> {
+    let scheduled: Option<ScheduledMessage> = sqlx::query_as(&format!(
+        r#"
+        SELECT {SCHEDULED_FIELDS}
+        FROM scheduled_messages
+        WHERE id = $1 AND sender_id = $2
+        FOR UPDATE
+        "#,
+    ))
+    .bind(id)
+    .bind(user_id)
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    match scheduled {
+        None => Err(AppError::NotFound(format!(
+            "Scheduled message with id={} not exist.",
+            id
+        ))),
+        Some(scheduled) if scheduled.message_id.is_some() => Err(AppError::ScheduleMessageError(
+            format!("Scheduled message {} has been sent.", id),
+        )),
+        Some(scheduled) => Ok(scheduled),
+    }
+}
+
+fn check_send_at(send_at: DateTime<Utc>) -> Result<(), AppError> {
+    if send_at <= Utc::now() {
+        return Err(AppError::ScheduleMessageError(
+            "Send time must be in the future.".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+// only what scheduled_messages stores is sent later, nothing may be dropped silently
+fn check_schedulable(message: &CreateMessage) -> Result<(), AppError> {
+    check_not_command(&message.content)?;
+    let unsupported = if message.poll.is_some() {
+        "Polls"
+    } else if message.client_msg_id.is_some() {
+        "Client message ids"
+    } else if message.expires_at.is_some() {
+        "Expiring messages"
+    } else {
+        return Ok(());
+    };
+
+    Err(AppError::ScheduleMessageError(format!(
+        "{} can not be scheduled.",
+        unsupported
+    )))
+}
+
+// the reply of a command has nobody to go to when it is sent later
+fn check_not_command(content: &str) -> Result<(), AppError> {
+    if parse_command(content).is_some() {
+        return Err(AppError::ScheduleMessageError(
+            "Slash commands can not be scheduled, start the message with // to send it as text."
+                .to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+impl ScheduleMessage {
+    pub fn new(content: &str, send_at: DateTime<Utc>) -> Self {
+        Self {
+            message: CreateMessage::new(content, &[]),
+            send_at,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{Ok, Result};
+    use chrono::Duration;
+
+    use crate::{models::ListMessages, AppConfig};
+
+    use super::*;
+
+    // make a scheduled message due without waiting for it
+    async fn make_due(state: &AppState, id: i64) -> Result<()> {
+        sqlx::query(
+            "UPDATE scheduled_messages SET send_at = NOW() - INTERVAL '1 second' WHERE id = $1",
+        )
+        .bind(id)
+        .execute(&state.pool)
+        .await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn scheduled_messages_should_be_sent_once() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+        let later = Utc::now() + Duration::hours(1);
+
+        let input = ScheduleMessage::new("good morning @user2", later);
+        let scheduled = state.schedule_message(input, 1, 1).await?;
+        let input = ScheduleMessage::new("later", later + Duration::hours(1));
+        state.schedule_message(input, 1, 1).await?;
+
+        // nothing is due yet
+        assert_eq!(state.dispatch_scheduled_messages().await?, 0);
+
+        make_due(&state, scheduled.id).await?;
+        let (a, b) = tokio::join!(
+            state.dispatch_scheduled_messages(),
+            state.dispatch_scheduled_messages()
+        );
+        assert_eq!(a? + b?, 1);
+        assert_eq!(state.dispatch_scheduled_messages().await?, 0);
+
+        let messages = state.list_messages(ListMessages::default(), 1).await?;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].content, "good morning @user2");
+        assert_eq!(messages[0].sender_id, 1);
+
+        // the delivered message is sent and can not be changed anymore
+        let pending = state.list_scheduled_messages(1).await?;
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].content, "later");
+        let ret = state.cancel_scheduled_message(scheduled.id, 1).await;
+        assert!(matches!(ret, Err(AppError::ScheduleMessageError(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn scheduled_message_should_fail_when_sender_left() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+        let later = Utc::now() + Duration::hours(1);
+
+        // chat 4 has members 1, 3, 4
+        let input = ScheduleMessage::new("bye", later);
+        let scheduled = state.schedule_message(input, 4, 4).await?;
+        state.leave_chat(4, 4).await?;
+        make_due(&state, scheduled.id).await?;
+
+        assert_eq!(state.dispatch_scheduled_messages().await?, 1);
+        assert!(state
+            .list_messages(ListMessages::default(), 4)
+            .await?
+            .is_empty());
+        let pending = state.list_scheduled_messages(4).await?;
+        assert!(pending[0].error.is_some());
+        assert_eq!(state.dispatch_scheduled_messages().await?, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn scheduled_message_rejected_by_database_should_not_block_others() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+        let later = Utc::now() + Duration::hours(1);
+        sqlx::query("ALTER TABLE messages ADD CONSTRAINT no_poison CHECK (content <> 'poison')")
+            .execute(&state.pool)
+            .await?;
+
+        let poison = state
+            .schedule_message(ScheduleMessage::new("poison", later), 1, 1)
+            .await?;
+        let fine = state
+            .schedule_message(ScheduleMessage::new("fine", later), 1, 1)
+            .await?;
+        make_due(&state, poison.id).await?;
+        make_due(&state, fine.id).await?;
+
+        assert_eq!(state.dispatch_scheduled_messages().await?, 2);
+        let messages = state.list_messages(ListMessages::default(), 1).await?;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].content, "fine");
+        let pending = state.list_scheduled_messages(1).await?;
+        assert_eq!(pending.len(), 1);
+        assert!(pending[0].error.as_ref().unwrap().contains("no_poison"));
+        assert_eq!(state.dispatch_scheduled_messages().await?, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn scheduled_message_should_be_edited_and_canceled() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+        let later = Utc::now() + Duration::hours(1);
+
+        let ret = state
+            .schedule_message(ScheduleMessage::new("too late", Utc::now()), 1, 1)
+            .await;
+        assert!(matches!(ret, Err(AppError::ScheduleMessageError(_))));
+        let mut input = ScheduleMessage::new("once", later);
+        input.message.client_msg_id = Some("a6f0c2".to_string());
+        let ret = state.schedule_message(input, 1, 1).await;
+        assert!(matches!(ret, Err(AppError::ScheduleMessageError(_))));
+        let mut input = ScheduleMessage::new("secret", later);
+        input.message.expires_at = Some(later + Duration::hours(1));
+        let ret = state.schedule_message(input, 1, 1).await;
+        assert!(matches!(ret, Err(AppError::ScheduleMessageError(_))));
+        let ret = state
+            .schedule_message(ScheduleMessage::new("/leave", later), 1, 1)
+            .await;
+        assert!(matches!(ret, Err(AppError::ScheduleMessageError(_))));
+        let escaped = state
+            .schedule_message(ScheduleMessage::new("//leave is a command", later), 1, 1)
+            .await?;
+        assert_eq!(escaped.content, "/leave is a command");
+
+        let scheduled = state
+            .schedule_message(ScheduleMessage::new("draft", later), 1, 1)
+            .await?;
+        let input = UpdateScheduledMessage {
+            content: Some("final".to_string()),
+            send_at: Some(later + Duration::minutes(5)),
+            ..Default::default()
+        };
+        let updated = state
+            .update_scheduled_message(input.clone(), scheduled.id, 1)
+            .await?;
+        assert_eq!(updated.content, "final");
+        assert_eq!(updated.send_at, scheduled.send_at + Duration::minutes(5));
+
+        // only the sender can see or change it
+        let ret = state.update_scheduled_message(input, scheduled.id, 2).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+        let input = UpdateScheduledMessage {
+            content: Some(" ".to_string()),
+            ..Default::default()
+        };
+        let ret = state.update_scheduled_message(input, scheduled.id, 1).await;
+        assert!(matches!(ret, Err(AppError::ScheduleMessageError(_))));
+        let input = UpdateScheduledMessage {
+            content: Some("/topic final".to_string()),
+            ..Default::default()
+        };
+        let ret = state.update_scheduled_message(input, scheduled.id, 1).await;
+        assert!(matches!(ret, Err(AppError::ScheduleMessageError(_))));
+
+        state.cancel_scheduled_message(scheduled.id, 1).await?;
+        state.cancel_scheduled_message(escaped.id, 1).await?;
+        assert!(state.list_scheduled_messages(1).await?.is_empty());
+
+        Ok(())
+    }
+}
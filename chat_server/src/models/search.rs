@@ -0,0 +1,267 @@
+use chat_core::SearchHit;
+use chrono::{Days, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+use crate::{error::AppError, AppState};
+
+use super::message::MESSAGE_FIELDS;
+
+const DEFAULT_PAGE_SIZE: u64 = 20;
+const MAX_PAGE_SIZE: u64 = 100;
+
+// snippets are html, the content is escaped so only the highlighting is markup
+const ESCAPED_CONTENT: &str = "replace(replace(replace(replace(content, '&', '&amp;'), \
+    '<', '&lt;'), '>', '&gt;'), '\"', '&quot;')";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SearchMessages {
+    pub q: String,
+    // id of the last hit of the previous page
+    pub cursor: Option<i64>,
+    pub limit: Option<u64>,
+}
+
+/// A search query split into its filters and the remaining text, e.g.
+/// `deploy from:alice in:general after:2024-08-01 has:image`.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct SearchQuery {
+    text: String,
+    from: Option<String>,
+    chat: Option<String>,
+    before: Option<NaiveDate>,
+    after: Option<NaiveDate>,
+    has_image: bool,
+}
+
+impl AppState {
+    /// Search messages in the chats the user is a member of, newest first.
+    pub async fn search_messages(
+        &self,
+        input: SearchMessages,
+        ws_id: i64,
+        user_id: i64,
+    ) -> Result<Vec<SearchHit>, AppError> {
+        let limit = match input.limit {
+            Some(0) => {
+                return Err(AppError::SearchError(
+                    "Limit must be greater than 0.".to_string(),
+                ))
+            }
+            Some(limit) => limit.min(MAX_PAGE_SIZE),
+            None => DEFAULT_PAGE_SIZE,
+        };
+        let query = SearchQuery::parse(&input.q).map_err(AppError::SearchError)?;
+        if query == SearchQuery::default() {
+            return Err(AppError::SearchError(
+                "Search query can not be empty.".to_string(),
+            ));
+        }
+
+        // `before:` is exclusive of the day, `after:` starts the day after
+        let before = query
+            .before
+            .map(|d| d.and_hms_opt(0, 0, 0).unwrap().and_utc());
+        let after = query
+            .after
+            .and_then(|d| d.checked_add_days(Days::new(1)))
+            .map(|d| d.and_hms_opt(0, 0, 0).unwrap().and_utc());
+
+        // the tsquery is written inline so the planner can pick the gin index, the
+        // page is picked first and snippets are only built for it
+        let (matches, snippet) = if query.text.is_empty() {
+            (
+                "$1 = ''",
+                ESCAPED_CONTENT.replace("(content", "(left(content, 200)"),
+            )
+        } else {
+            (
+                "m.content_tsv @@ websearch_to_tsquery('english', $1)",
+                format!("ts_headline('english', {ESCAPED_CONTENT}, websearch_to_tsquery('english', $1), 'StartSel=<mark>, StopSel=</mark>, MaxFragments=2')"),
+            )
+        };
+        let hits = sqlx::query_as(&format!(
+            r#"
+            WITH hits AS (
+                SELECT {MESSAGE_FIELDS}
+                FROM messages m
+                WHERE {matches}
+                    AND m.chat_id IN (
+                        SELECT cm.chat_id
+                        FROM chat_members cm
+                        JOIN chats c ON c.id = cm.chat_id
+                        WHERE cm.user_id = $3 AND c.ws_id = $2
+                            AND ($5::TEXT IS NULL OR c.name = $5 OR c.id::TEXT = $5)
+                    )
+                    AND m.deleted_at IS NULL
+                    AND ($4::TEXT IS NULL OR m.sender_id IN (
+                        SELECT id FROM users WHERE ws_id = $2 AND lower(split_part(email, '@', 1)) = $4
+                    ))
+                    AND ($6::TIMESTAMPTZ IS NULL OR m.created_at < $6)
+                    AND ($7::TIMESTAMPTZ IS NULL OR m.created_at >= $7)
+                    AND (NOT $8 OR cardinality(m.images) > 0)
+                    AND ($9::BIGINT IS NULL OR m.id < $9)
+                ORDER BY m.id DESC
+                LIMIT $10
+            )
+            SELECT *, {snippet} AS snippet
+            FROM hits
+            ORDER BY id DESC
+            "#,
+        ))
+        .bind(&query.text)
+        .bind(ws_id)
+        .bind(user_id)
+        .bind(query.from)
+        .bind(query.chat)
+        .bind(before)
+        .bind(after)
+        .bind(query.has_image)
+        .bind(input.cursor)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(hits)
+    }
+}
+
+impl SearchQuery {
+    fn parse(q: &str) -> Result<Self, String> {
+        let mut query = SearchQuery::default();
+        let mut words = vec![];
+        for word in q.split_whitespace() {
+            let Some((key, value)) = word.split_once(':') else {
+                words.push(word);
+                continue;
+            };
+            match key {
+                "from" => query.from = Some(value.trim_start_matches('@').to_lowercase()),
+                "in" => query.chat = Some(value.trim_start_matches('#').to_string()),
+                "before" => query.before = Some(parse_date(value)?),
+                "after" => query.after = Some(parse_date(value)?),
+                "has" if value == "image" => query.has_image = true,
+                "has" => return Err(format!("Unknown filter has:{}.", value)),
+                // a colon in plain text, e.g. a time or an url
+                _ => words.push(word),
+            }
+        }
+        query.text = words.join(" ");
+
+        Ok(query)
+    }
+}
+
+fn parse_date(s: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map_err(|_| format!("Invalid date {}, expect YYYY-MM-DD.", s))
+}
+
+#[cfg(test)]
+impl SearchMessages {
+    pub fn new(q: &str) -> Self {
+        Self {
+            q: q.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{Ok, Result};
+
+    use crate::{models::CreateMessage, AppConfig};
+
+    use super::*;
+
+    #[test]
+    fn search_query_should_parse() -> Result<()> {
+        let query = SearchQuery::parse(
+            "deploy  from:@User2 in:#general after:2024-08-01 before:2024-09-01 has:image at 10:30",
+        )
+        .unwrap();
+        assert_eq!(
+            query,
+            SearchQuery {
+                text: "deploy at 10:30".to_string(),
+                from: Some("user2".to_string()),
+                chat: Some("general".to_string()),
+                before: NaiveDate::from_ymd_opt(2024, 9, 1),
+                after: NaiveDate::from_ymd_opt(2024, 8, 1),
+                has_image: true,
+            }
+        );
+
+        assert!(SearchQuery::parse("before:yesterday").is_err());
+        assert!(SearchQuery::parse("has:video").is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn search_messages_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+
+        let input = CreateMessage::new("deploying the new release", &[]);
+        let msg1 = state.create_message(input, 1, 1).await?;
+        let input = CreateMessage::new("the release notes", &["/files/1/notes.png"]);
+        let msg2 = state.create_message(input, 1, 2).await?;
+        // chat 3 only has members 1 and 2
+        let input = CreateMessage::new("private release", &[]);
+        let msg3 = state.create_message(input, 3, 1).await?;
+
+        let hits = state
+            .search_messages(SearchMessages::new("releases"), 1, 1)
+            .await?;
+        let ids: Vec<_> = hits.iter().map(|h| h.message.id).collect();
+        assert_eq!(ids, vec![msg3.id, msg2.id, msg1.id]);
+        assert_eq!(hits[0].snippet, "private <mark>release</mark>");
+
+        // members of other chats see nothing of chat 3
+        let hits = state
+            .search_messages(SearchMessages::new("release"), 1, 3)
+            .await?;
+        assert_eq!(hits.len(), 2);
+
+        let hits = state
+            .search_messages(SearchMessages::new("release from:user2"), 1, 1)
+            .await?;
+        assert_eq!(hits[0].message.id, msg2.id);
+        assert_eq!(hits.len(), 1);
+
+        let hits = state
+            .search_messages(SearchMessages::new("has:image in:general"), 1, 1)
+            .await?;
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].snippet, "the release notes");
+
+        let hits = state
+            .search_messages(SearchMessages::new("release before:2000-01-01"), 1, 1)
+            .await?;
+        assert!(hits.is_empty());
+
+        let input = SearchMessages {
+            cursor: Some(msg2.id),
+            ..SearchMessages::new("release")
+        };
+        let hits = state.search_messages(input, 1, 1).await?;
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].message.id, msg1.id);
+
+        // markup in messages does not make it into snippets
+        let input = CreateMessage::new("<img src=x onerror=alert(1)> release & \"notes\"", &[]);
+        state.create_message(input, 1, 1).await?;
+        let hits = state
+            .search_messages(SearchMessages::new("release"), 1, 1)
+            .await?;
+        assert!(!hits[0].snippet.contains("<img"));
+        assert!(hits[0]
+            .snippet
+            .contains("&gt; <mark>release</mark> &amp; &quot;notes"));
+
+        let ret = state.search_messages(SearchMessages::new(" "), 1, 1).await;
+        assert!(matches!(ret, Err(AppError::SearchError(_))));
+
+        Ok(())
+    }
+}
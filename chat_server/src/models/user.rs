@@ -1,11 +1,10 @@
-use super::{ChatUser, Workspace};
-use crate::{error::AppError, User};
+use crate::{error::AppError, AppState};
 use argon2::{
     password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, SaltString},
     Argon2, PasswordVerifier,
 };
+use chat_core::{ChatUser, User};
 use serde::Deserialize;
-use sqlx::PgPool;
 use std::mem;
 
 #[derive(Debug, Deserialize)]
@@ -22,27 +21,48 @@ pub struct SigninUser {
     pub password: String,
 }
 
-impl User {
-    pub async fn find_by_email(email: &str, pool: &PgPool) -> Result<Option<Self>, AppError> {
+impl AppState {
+    pub async fn find_user_by_email(&self, email: &str) -> Result<Option<User>, AppError> {
         let user = sqlx::query_as(
             r#"SELECT id, ws_id, fullname, email, created_at FROM users WHERE email = $1"#,
         )
         .bind(email)
-        .fetch_optional(pool)
+        .fetch_optional(&self.pool)
         .await?;
         Ok(user)
     }
 
-    pub async fn create(input: &CreateUser, pool: &PgPool) -> Result<Self, AppError> {
-        if User::find_by_email(&input.email, pool).await?.is_some() {
+    /// Users of a workspace addressed by the name part of their email, as in mentions.
+    pub async fn find_users_by_names(
+        &self,
+        ws_id: i64,
+        names: &[String],
+    ) -> Result<Vec<User>, AppError> {
+        let users = sqlx::query_as(
+            r#"
+            SELECT id, ws_id, fullname, email, created_at
+            FROM users
+            WHERE ws_id = $1 AND lower(split_part(email, '@', 1)) = ANY($2)
+            ORDER BY id
+            "#,
+        )
+        .bind(ws_id)
+        .bind(names)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(users)
+    }
+
+    pub async fn create_user(&self, input: &CreateUser) -> Result<User, AppError> {
+        if self.find_user_by_email(&input.email).await?.is_some() {
             return Err(AppError::EmailAlreadyExists(input.email.clone()));
         }
 
         let password_hash = hash_password(&input.password)?;
 
-        let ws = match Workspace::find_by_name(&input.workspace, pool).await? {
+        let ws = match self.find_workspace_by_name(&input.workspace).await? {
             Some(ws) => ws,
-            None => Workspace::create(&input.workspace, 0, pool).await?,
+            None => self.create_workspace(&input.workspace, 0).await?,
         };
 
         let ws_id = ws.id;
@@ -57,22 +77,23 @@ impl User {
         .bind(&input.email)
         .bind(&input.fullname)
         .bind(password_hash)
-        .fetch_one(pool)
+        .fetch_one(&self.pool)
         .await?;
 
         if ws.owner_id == 0 {
-            ws.update_owner(user.id as _, pool).await?;
+            self.update_workspace_owner(ws.id as _, user.id as _)
+                .await?;
         }
 
         Ok(user)
     }
 
-    pub async fn verify(input: &SigninUser, pool: &PgPool) -> Result<Option<Self>, AppError> {
+    pub async fn verify_user(&self, input: &SigninUser) -> Result<Option<User>, AppError> {
         let user: Option<User> = sqlx::query_as(
             "SELECT id, ws_id, email, fullname, password_hash, created_at FROM users WHERE email = $1",
         )
         .bind(&input.email)
-        .fetch_optional(pool)
+        .fetch_optional(&self.pool)
         .await?;
 
         match user {
@@ -87,10 +108,8 @@ impl User {
             None => Ok(None),
         }
     }
-}
 
-impl ChatUser {
-    pub async fn fetch_by_ids(ids: &[i64], pool: &PgPool) -> Result<Vec<Self>, AppError> {
+    pub async fn fetch_chat_users_by_ids(&self, ids: &[i64]) -> Result<Vec<ChatUser>, AppError> {
         if ids.is_empty() {
             return Err(AppError::CreateChatError("users is empty".to_string()));
         }
@@ -103,23 +122,7 @@ impl ChatUser {
             "#,
         )
         .bind(ids)
-        .fetch_all(pool)
-        .await?;
-
-        Ok(users)
-    }
-
-    #[allow(unused)]
-    pub async fn fetch_all(ws_id: u64, pool: &PgPool) -> Result<Vec<Self>, AppError> {
-        let users = sqlx::query_as(
-            r#"
-            SELECT id, fullname, email
-            FROM users
-            WHERE ws_id = $1
-            "#,
-        )
-        .bind(ws_id as i64)
-        .fetch_all(pool)
+        .fetch_all(&self.pool)
         .await?;
 
         Ok(users)
@@ -149,20 +152,6 @@ fn verify_password(password: &str, password_hash: &str) -> Result<bool, AppError
     Ok(is_valid)
 }
 
-#[cfg(test)]
-impl User {
-    pub fn new(id: i64, fullname: &str, email: &str) -> Self {
-        Self {
-            id,
-            ws_id: 0,
-            fullname: fullname.to_string(),
-            email: email.to_string(),
-            password_hash: None,
-            created_at: chrono::Utc::now(),
-        }
-    }
-}
-
 #[cfg(test)]
 impl CreateUser {
     pub fn new(ws: &str, fullname: &str, email: &str, password: &str) -> Self {
@@ -188,7 +177,7 @@ impl SigninUser {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::test_util::get_test_pool;
+    use crate::AppConfig;
     use anyhow::{Ok, Result};
 
     #[test]
@@ -203,23 +192,23 @@ mod tests {
 
     #[tokio::test]
     async fn create_user_and_verify_user_should_work() -> Result<()> {
-        let (_tdb, pool) = get_test_pool(None).await;
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
 
         let input = CreateUser::new("acme1", "Monkey D. Luffy", "luffy@acme.org", "hunter42");
 
-        let user = User::create(&input, &pool).await?;
+        let user = state.create_user(&input).await?;
         assert_eq!(user.email, input.email);
         assert_eq!(user.fullname, input.fullname);
         assert!(user.id > 0);
 
-        let user = User::find_by_email(&input.email, &pool).await?;
+        let user = state.find_user_by_email(&input.email).await?;
         assert!(user.is_some());
         let user = user.unwrap();
         assert_eq!(user.email, input.email);
         assert_eq!(user.fullname, input.fullname);
 
         let input = SigninUser::new(&input.email, &input.password);
-        let user = User::verify(&input, &pool).await?;
+        let user = state.verify_user(&input).await?;
         assert!(user.is_some());
         let user = user.unwrap();
         assert_eq!(user.email, input.email);
@@ -229,12 +218,12 @@ mod tests {
 
     #[tokio::test]
     async fn create_duplicate_user_should_fail() -> Result<()> {
-        let (_tdb, pool) = get_test_pool(None).await;
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
 
         let input = CreateUser::new("acme2", "Monkey D. Luffy", "", "hunter42");
-        let user = User::create(&input, &pool).await?;
+        let user = state.create_user(&input).await?;
         assert_eq!(user.email, input.email);
-        let ret = User::create(&input, &pool).await;
+        let ret = state.create_user(&input).await;
         match ret {
             Err(AppError::EmailAlreadyExists(email)) => {
                 assert_eq!(email, input.email);
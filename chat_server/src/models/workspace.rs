@@ -1,72 +1,114 @@
-use super::{ChatUser, Workspace};
-use crate::error::AppError;
-use sqlx::PgPool;
+use crate::{error::AppError, AppState};
+use chat_core::{ChatUser, Workspace};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpdateWorkspace {
+    // seconds, null removes the limit
+    pub edit_window_secs: Option<i64>,
+}
 
-impl Workspace {
-    pub async fn create(name: &str, user_id: u64, pool: &PgPool) -> Result<Self, AppError> {
+impl AppState {
+    pub async fn create_workspace(&self, name: &str, user_id: u64) -> Result<Workspace, AppError> {
         let ws = sqlx::query_as(
             r#"
             INSERT INTO workspaces (name, owner_id)
             VALUES ($1, $2)
-            RETURNING id, name, owner_id, created_at
+            RETURNING id, name, owner_id, edit_window_secs, created_at
         "#,
         )
         .bind(name)
         .bind(user_id as i64)
-        .fetch_one(pool)
+        .fetch_one(&self.pool)
         .await?;
 
         Ok(ws)
     }
 
-    pub async fn update_owner(&self, owner_id: u64, pool: &PgPool) -> Result<Self, AppError> {
+    pub async fn update_workspace_owner(
+        &self,
+        id: u64,
+        owner_id: u64,
+    ) -> Result<Workspace, AppError> {
         let ws = sqlx::query_as(
             r#"
             UPDATE workspaces SET owner_id=$1
             WHERE id = $2 and (SELECT ws_id FROM users where id = $1) = $2
-            RETURNING id, name, owner_id, created_at
+            RETURNING id, name, owner_id, edit_window_secs, created_at
         "#,
         )
         .bind(owner_id as i64)
-        .bind(self.id)
-        .fetch_one(pool)
+        .bind(id as i64)
+        .fetch_one(&self.pool)
         .await?;
 
         Ok(ws)
     }
 
-    pub async fn find_by_name(name: &str, pool: &PgPool) -> Result<Option<Self>, AppError> {
+    pub async fn update_workspace(
+        &self,
+        id: i64,
+        input: UpdateWorkspace,
+    ) -> Result<Workspace, AppError> {
+        if input.edit_window_secs.is_some_and(|secs| secs < 0) {
+            return Err(AppError::UpdateWorkspaceError(
+                "Edit window can not be negative.".to_string(),
+            ));
+        }
+
         let ws = sqlx::query_as(
             r#"
-            SELECT id, name, owner_id, created_at
+            UPDATE workspaces SET edit_window_secs=$2
+            WHERE id = $1
+            RETURNING id, name, owner_id, edit_window_secs, created_at
+        "#,
+        )
+        .bind(id)
+        .bind(input.edit_window_secs)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        match ws {
+            Some(ws) => Ok(ws),
+            None => Err(AppError::NotFound(format!(
+                "Workspace with id={} not exist.",
+                id
+            ))),
+        }
+    }
+
+    pub async fn find_workspace_by_name(&self, name: &str) -> Result<Option<Workspace>, AppError> {
+        let ws = sqlx::query_as(
+            r#"
+            SELECT id, name, owner_id, edit_window_secs, created_at
             FROM workspaces
             WHERE name = $1
         "#,
         )
         .bind(name)
-        .fetch_optional(pool)
+        .fetch_optional(&self.pool)
         .await?;
 
         Ok(ws)
     }
 
-    #[allow(unused)]
-    pub async fn find_by_id(id: i64, pool: &PgPool) -> Result<Option<Self>, AppError> {
+    pub async fn find_workspace_by_id(&self, id: i64) -> Result<Option<Workspace>, AppError> {
         let ws = sqlx::query_as(
             r#"
-            SELECT id, name, owner_id, created_at
+            SELECT id, name, owner_id, edit_window_secs, created_at
             FROM workspaces
             WHERE id = $1
         "#,
         )
         .bind(id)
-        .fetch_optional(pool)
+        .fetch_optional(&self.pool)
         .await?;
 
         Ok(ws)
     }
 
-    pub async fn fetch_all_chat_users(id: u64, pool: &PgPool) -> Result<Vec<ChatUser>, AppError> {
+    pub async fn fetch_chat_users(&self, ws_id: u64) -> Result<Vec<ChatUser>, AppError> {
         let users = sqlx::query_as(
             r#"
             SELECT id, fullname, email
@@ -76,32 +118,41 @@ impl Workspace {
             ORDER BY id
         "#,
         )
-        .bind(id as i64)
-        .fetch_all(pool)
+        .bind(ws_id as i64)
+        .fetch_all(&self.pool)
         .await?;
 
         Ok(users)
     }
 }
 
+#[cfg(test)]
+impl UpdateWorkspace {
+    pub fn new(edit_window_secs: Option<i64>) -> Self {
+        Self { edit_window_secs }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{models::CreateUser, test_util::get_test_pool, User};
+    use crate::{models::CreateUser, AppConfig};
     use anyhow::{Ok, Result};
 
     #[tokio::test]
     async fn workspace_create_and_set_owner_should_work() -> Result<()> {
-        let (_tdb, pool) = get_test_pool(None).await;
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
 
-        let ws = Workspace::create("test", 0, &pool).await?;
+        let ws = state.create_workspace("test", 0).await?;
         assert_eq!(ws.name, "test");
 
         let input = CreateUser::new(&ws.name, "test user", "testUser@acme.org", "hunter42");
-        let user = User::create(&input, &pool).await?;
+        let user = state.create_user(&input).await?;
         assert_eq!(user.ws_id, ws.id);
 
-        let ws = ws.update_owner(user.id as _, &pool).await?;
+        let ws = state
+            .update_workspace_owner(ws.id as _, user.id as _)
+            .await?;
         assert_eq!(ws.owner_id, user.id);
         assert_eq!(user.ws_id, ws.id);
 
@@ -110,9 +161,9 @@ mod tests {
 
     #[tokio::test]
     async fn workspace_find_by_id_should_work() -> Result<()> {
-        let (_tdb, pool) = get_test_pool(None).await;
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
 
-        let ws = Workspace::find_by_id(1, &pool).await?;
+        let ws = state.find_workspace_by_id(1).await?;
         assert!(ws.is_some());
         assert_eq!(ws.unwrap().name, "workspace1");
         Ok(())
@@ -120,9 +171,9 @@ mod tests {
 
     #[tokio::test]
     async fn workspace_find_by_name_should_work() -> Result<()> {
-        let (_tdb, pool) = get_test_pool(None).await;
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
 
-        let ws = Workspace::find_by_name("workspace2", &pool).await?;
+        let ws = state.find_workspace_by_name("workspace2").await?;
         assert!(ws.is_some());
         assert_eq!(ws.unwrap().name, "workspace2");
         Ok(())
@@ -130,11 +181,36 @@ mod tests {
 
     #[tokio::test]
     async fn workspace_fetch_all_chat_users_should_work() -> Result<()> {
-        let (_tdb, pool) = get_test_pool(None).await;
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
 
-        let users = Workspace::fetch_all_chat_users(1, &pool).await?;
+        let users = state.fetch_chat_users(1).await?;
         assert_eq!(users.len(), 5);
         assert_eq!(users[2].fullname, "user3");
         Ok(())
     }
+
+    #[tokio::test]
+    async fn workspace_update_edit_window_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+
+        let ws = state
+            .update_workspace(1, UpdateWorkspace::new(Some(600)))
+            .await?;
+        assert_eq!(ws.edit_window_secs, Some(600));
+        let ws = state
+            .update_workspace(1, UpdateWorkspace::new(None))
+            .await?;
+        assert_eq!(ws.edit_window_secs, None);
+
+        let ret = state
+            .update_workspace(1, UpdateWorkspace::new(Some(-1)))
+            .await;
+        assert!(matches!(ret, Err(AppError::UpdateWorkspaceError(_))));
+        let ret = state
+            .update_workspace(100, UpdateWorkspace::new(None))
+            .await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        Ok(())
+    }
 }
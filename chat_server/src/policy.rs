@@ -0,0 +1,179 @@
+use chat_core::{Chat, ChatMemberRole, ChatType, User};
+
+use crate::{error::AppError, AppState};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ChatAction {
+    Read,
+    Write,
+    Admin,
+    Delete,
+}
+
+/// Access rules of a chat for a user. Chats of other workspaces are never accessible.
+pub(crate) trait Policy {
+    /// Members can read a chat, and anyone in the workspace can read a public channel.
+    async fn can_read_chat(&self, user: &User, chat: &Chat) -> Result<bool, AppError>;
+
+    /// Only members can post to a chat.
+    async fn can_write_chat(&self, user: &User, chat: &Chat) -> Result<bool, AppError>;
+
+    /// Chat owner, chat admins and the workspace owner can manage a chat.
+    async fn can_admin_chat(&self, user: &User, chat: &Chat) -> Result<bool, AppError>;
+
+    /// Only the workspace owner can delete a chat.
+    async fn can_delete_chat(&self, user: &User, chat: &Chat) -> Result<bool, AppError>;
+}
+
+pub(crate) struct ChatPolicy<'a> {
+    state: &'a AppState,
+}
+
+impl<'a> ChatPolicy<'a> {
+    pub(crate) fn new(state: &'a AppState) -> Self {
+        Self { state }
+    }
+
+    /// Load the chat and check the action against it. A chat which does not exist or
+    /// lives in another workspace is reported as not found, so its existence is not
+    /// leaked; a visible chat the user may not act on is reported as forbidden.
+    pub(crate) async fn authorize(
+        &self,
+        user: &User,
+        chat_id: i64,
+        action: ChatAction,
+    ) -> Result<Chat, AppError> {
+        let chat = match self.state.find_chat_by_id(chat_id).await? {
+            Some(chat) if chat.ws_id == user.ws_id => chat,
+            _ => {
+                return Err(AppError::NotFound(format!(
+                    "Chat with id={} not exist.",
+                    chat_id
+                )))
+            }
+        };
+
+        let allowed = match action {
+            ChatAction::Read => self.can_read_chat(user, &chat).await?,
+            ChatAction::Write => self.can_write_chat(user, &chat).await?,
+            ChatAction::Admin => self.can_admin_chat(user, &chat).await?,
+            ChatAction::Delete => self.can_delete_chat(user, &chat).await?,
+        };
+        if !allowed {
+            return Err(AppError::ChatAccessDenied(format!(
+                "User {} can not {:?} chat {}.",
+                user.id, action, chat_id
+            )));
+        }
+
+        Ok(chat)
+    }
+}
+
+impl Policy for ChatPolicy<'_> {
+    async fn can_read_chat(&self, user: &User, chat: &Chat) -> Result<bool, AppError> {
+        Ok(chat.ws_id == user.ws_id
+            && (chat.r#type == ChatType::PublicChannel || chat.members.contains(&user.id)))
+    }
+
+    async fn can_write_chat(&self, user: &User, chat: &Chat) -> Result<bool, AppError> {
+        Ok(chat.ws_id == user.ws_id && chat.members.contains(&user.id))
+    }
+
+    async fn can_admin_chat(&self, user: &User, chat: &Chat) -> Result<bool, AppError> {
+        if chat.ws_id != user.ws_id {
+            return Ok(false);
+        }
+
+        let role = self.state.chat_member_role(chat.id, user.id).await?;
+        if matches!(role, Some(ChatMemberRole::Owner | ChatMemberRole::Admin)) {
+            return Ok(true);
+        }
+
+        self.can_delete_chat(user, chat).await
+    }
+
+    async fn can_delete_chat(&self, user: &User, chat: &Chat) -> Result<bool, AppError> {
+        if chat.ws_id != user.ws_id {
+            return Ok(false);
+        }
+
+        let ws = self.state.find_workspace_by_id(chat.ws_id).await?;
+        Ok(ws.map(|ws| ws.owner_id == user.id).unwrap_or(false))
+    }
+}
+
+impl AppState {
+    pub(crate) fn policy(&self) -> ChatPolicy<'_> {
+        ChatPolicy::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AppConfig;
+    use anyhow::{Ok, Result};
+
+    #[derive(Debug, Clone, Copy)]
+    enum Expect {
+        Allowed,
+        Forbidden,
+        NotFound,
+    }
+
+    #[tokio::test]
+    async fn chat_policy_should_work() -> Result<()> {
+        use ChatAction::*;
+        use Expect::*;
+
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+        // make user5 a non-member of the public channel
+        let user5 = state.find_user_by_email("user5@acme.org").await?.unwrap();
+        state.leave_chat(1, user5.id).await?;
+
+        // chat 1: public channel, chat 2: private channel (user2 is admin),
+        // chat 3: single, chat 4: group (user3 is owner); user1 owns workspace1
+        let cases = [
+            ("user1@acme.org", 1, Read, Allowed),
+            ("user5@acme.org", 1, Read, Allowed),
+            ("user5@acme.org", 1, Write, Forbidden),
+            ("user5@acme.org", 1, Admin, Forbidden),
+            ("user5@acme.org", 2, Read, Forbidden),
+            ("user3@acme.org", 2, Write, Allowed),
+            ("user3@acme.org", 2, Admin, Forbidden),
+            ("user2@acme.org", 2, Admin, Allowed),
+            ("user4@acme.org", 3, Read, Forbidden),
+            ("user2@acme.org", 3, Write, Allowed),
+            ("user2@acme.org", 3, Admin, Forbidden),
+            ("user1@acme.org", 3, Admin, Allowed),
+            ("user3@acme.org", 4, Admin, Allowed),
+            ("user4@acme.org", 4, Admin, Forbidden),
+            // chat owners and admins can not delete the chat, only the workspace owner
+            ("user3@acme.org", 4, Delete, Forbidden),
+            ("user2@acme.org", 2, Delete, Forbidden),
+            ("user1@acme.org", 4, Delete, Allowed),
+            ("super@none.org", 1, Read, NotFound),
+            ("super@none.org", 4, Admin, NotFound),
+            ("user1@acme.org", 100, Read, NotFound),
+        ];
+
+        let policy = state.policy();
+        for (email, chat_id, action, expect) in cases {
+            let user = state.find_user_by_email(email).await?.unwrap();
+            let ret = policy.authorize(&user, chat_id, action).await;
+            let matched = match expect {
+                Allowed => ret.is_ok(),
+                Forbidden => matches!(ret, Err(AppError::ChatAccessDenied(_))),
+                NotFound => matches!(ret, Err(AppError::NotFound(_))),
+            };
+            assert!(
+                matched,
+                "{} {:?} chat {}: expect {:?}, got {:?}",
+                email, action, chat_id, expect, ret
+            );
+        }
+
+        Ok(())
+    }
+}
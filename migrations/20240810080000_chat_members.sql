@@ -0,0 +1,28 @@
+-- Add migration script here
+-- create chat member role: owner, admin, member
+CREATE TYPE chat_member_role AS ENUM ('owner', 'admin', 'member');
+-- create chat member table
+CREATE TABLE IF NOT EXISTS chat_members (
+    chat_id BIGINT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    role chat_member_role NOT NULL DEFAULT 'member',
+    joined_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+    last_read_message_id BIGINT REFERENCES messages(id) ON DELETE SET NULL,
+    PRIMARY KEY (chat_id, user_id)
+);
+-- create index for chat members for user_id, to find chats of a user
+CREATE INDEX IF NOT EXISTS chat_members_user_id_idx ON chat_members (user_id, chat_id);
+-- move existing members into chat_members
+INSERT INTO chat_members (chat_id, user_id, joined_at)
+SELECT DISTINCT chats.id,
+    member_id,
+    chats.created_at
+FROM chats,
+    unnest(chats.members) AS member_id
+WHERE EXISTS (
+        SELECT 1
+        FROM users
+        WHERE users.id = member_id
+    ) ON CONFLICT DO NOTHING;
+-- drop the old member id list
+ALTER TABLE chats DROP COLUMN members;
@@ -0,0 +1,73 @@
+-- Add migration script here
+-- notify chat events to notify_server through the chat_events channel,
+-- payloads only carry rows and ids, notify_server looks up chat members by itself
+-- chat created, deferred to commit so the chat is complete when notified
+CREATE OR REPLACE FUNCTION notify_chat_created() RETURNS TRIGGER AS $$
+BEGIN
+    PERFORM pg_notify(
+        'chat_events',
+        json_build_object('event', 'chat_created', 'chat', row_to_json(NEW))::text
+    );
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+CREATE CONSTRAINT TRIGGER chat_created_trigger
+AFTER INSERT ON chats
+DEFERRABLE INITIALLY DEFERRED
+FOR EACH ROW EXECUTE FUNCTION notify_chat_created();
+-- member added or removed
+CREATE OR REPLACE FUNCTION notify_chat_member_changed() RETURNS TRIGGER AS $$
+DECLARE
+    member RECORD;
+    event TEXT;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        -- members of a chat created in the same transaction are covered by chat_created
+        IF EXISTS (
+            SELECT 1 FROM chats
+            WHERE id = NEW.chat_id AND xmin = pg_current_xact_id()::xid
+        ) THEN
+            RETURN NULL;
+        END IF;
+        member := NEW;
+        event := 'member_added';
+    ELSE
+        member := OLD;
+        event := 'member_removed';
+    END IF;
+
+    PERFORM pg_notify(
+        'chat_events',
+        json_build_object(
+            'event', event,
+            'chat_id', member.chat_id,
+            'user_id', member.user_id
+        )::text
+    );
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+CREATE TRIGGER chat_member_changed_trigger
+AFTER INSERT OR DELETE ON chat_members
+FOR EACH ROW EXECUTE FUNCTION notify_chat_member_changed();
+-- new message, content is dropped when the payload is over the pg_notify limit (8000 bytes)
+CREATE OR REPLACE FUNCTION notify_message_created() RETURNS TRIGGER AS $$
+DECLARE
+    payload TEXT;
+BEGIN
+    payload := json_build_object('event', 'new_message', 'message', row_to_json(NEW))::text;
+    IF octet_length(payload) > 7900 THEN
+        payload := json_build_object(
+            'event', 'new_message',
+            'message', to_jsonb(NEW) || jsonb_build_object('content', '', 'images', '[]'::jsonb),
+            'truncated', TRUE
+        )::text;
+    END IF;
+
+    PERFORM pg_notify('chat_events', payload);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+CREATE TRIGGER message_created_trigger
+AFTER INSERT ON messages
+FOR EACH ROW EXECUTE FUNCTION notify_message_created();
@@ -0,0 +1,53 @@
+-- Add migration script here
+-- how long after sending a message can still be edited, NULL means no limit
+ALTER TABLE workspaces
+ADD COLUMN edit_window_secs BIGINT;
+ALTER TABLE messages
+ADD COLUMN edited_at timestamptz;
+-- previous revisions of edited messages, rows are never updated or deleted
+CREATE TABLE IF NOT EXISTS message_edits (
+    id BIGSERIAL PRIMARY KEY,
+    message_id BIGINT NOT NULL REFERENCES messages(id),
+    editor_id BIGINT NOT NULL REFERENCES users(id),
+    content TEXT NOT NULL,
+    images TEXT [],
+    -- when this revision was replaced
+    created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX IF NOT EXISTS message_edits_message_id_idx ON message_edits (message_id, id);
+-- notify new and edited messages with the same payload, content is dropped when the
+-- payload is over the pg_notify limit (8000 bytes)
+DROP TRIGGER IF EXISTS message_created_trigger ON messages;
+DROP FUNCTION IF EXISTS notify_message_created;
+CREATE OR REPLACE FUNCTION notify_message_changed() RETURNS TRIGGER AS $$
+DECLARE
+    event TEXT;
+    payload TEXT;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        event := 'new_message';
+    ELSE
+        event := 'message_updated';
+    END IF;
+
+    payload := json_build_object('event', event, 'message', row_to_json(NEW))::text;
+    IF octet_length(payload) > 7900 THEN
+        payload := json_build_object(
+            'event', event,
+            'message', to_jsonb(NEW) || jsonb_build_object('content', '', 'images', '[]'::jsonb),
+            'truncated', TRUE
+        )::text;
+    END IF;
+
+    PERFORM pg_notify('chat_events', payload);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+CREATE TRIGGER message_created_trigger
+AFTER INSERT ON messages
+FOR EACH ROW EXECUTE FUNCTION notify_message_changed();
+CREATE TRIGGER message_updated_trigger
+AFTER UPDATE OF content, images ON messages
+FOR EACH ROW
+WHEN (OLD.content IS DISTINCT FROM NEW.content OR OLD.images IS DISTINCT FROM NEW.images)
+EXECUTE FUNCTION notify_message_changed();
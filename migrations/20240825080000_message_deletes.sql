@@ -0,0 +1,34 @@
+-- Add migration script here
+-- retracted messages are kept as tombstones with blanked content, so the ordering
+-- cursors and references to them stay valid
+ALTER TABLE messages
+ADD COLUMN deleted_at timestamptz;
+-- edits of retracted messages are not notified as updates
+DROP TRIGGER IF EXISTS message_updated_trigger ON messages;
+CREATE TRIGGER message_updated_trigger
+AFTER UPDATE OF content, images ON messages
+FOR EACH ROW
+WHEN (
+    NEW.deleted_at IS NULL
+    AND (OLD.content IS DISTINCT FROM NEW.content OR OLD.images IS DISTINCT FROM NEW.images)
+)
+EXECUTE FUNCTION notify_message_changed();
+-- message retracted
+CREATE OR REPLACE FUNCTION notify_message_deleted() RETURNS TRIGGER AS $$
+BEGIN
+    PERFORM pg_notify(
+        'chat_events',
+        json_build_object(
+            'event', 'message_deleted',
+            'chat_id', NEW.chat_id,
+            'message_id', NEW.id
+        )::text
+    );
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+CREATE TRIGGER message_deleted_trigger
+AFTER UPDATE OF deleted_at ON messages
+FOR EACH ROW
+WHEN (OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL)
+EXECUTE FUNCTION notify_message_deleted();
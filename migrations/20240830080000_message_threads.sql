@@ -0,0 +1,6 @@
+-- Add migration script here
+-- replies of a thread point to its top level message, threads are not nested
+ALTER TABLE messages
+ADD COLUMN parent_id BIGINT REFERENCES messages(id);
+CREATE INDEX IF NOT EXISTS messages_parent_id_created_at_id_idx ON messages (parent_id, created_at, id)
+WHERE parent_id IS NOT NULL;
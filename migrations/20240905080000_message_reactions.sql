@@ -0,0 +1,41 @@
+-- Add migration script here
+-- one row per user and emoji on a message
+CREATE TABLE IF NOT EXISTS message_reactions (
+    message_id BIGINT NOT NULL REFERENCES messages(id),
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    emoji VARCHAR(64) NOT NULL,
+    created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (message_id, user_id, emoji)
+);
+-- reaction added or removed
+CREATE OR REPLACE FUNCTION notify_message_reaction_changed() RETURNS TRIGGER AS $$
+DECLARE
+    reaction RECORD;
+    event TEXT;
+    chat BIGINT;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        reaction := NEW;
+        event := 'reaction_added';
+    ELSE
+        reaction := OLD;
+        event := 'reaction_removed';
+    END IF;
+
+    SELECT chat_id INTO chat FROM messages WHERE id = reaction.message_id;
+    PERFORM pg_notify(
+        'chat_events',
+        json_build_object(
+            'event', event,
+            'chat_id', chat,
+            'message_id', reaction.message_id,
+            'user_id', reaction.user_id,
+            'emoji', reaction.emoji
+        )::text
+    );
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+CREATE TRIGGER message_reaction_changed_trigger
+AFTER INSERT OR DELETE ON message_reactions
+FOR EACH ROW EXECUTE FUNCTION notify_message_reaction_changed();
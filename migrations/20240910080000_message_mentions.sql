@@ -0,0 +1,34 @@
+-- Add migration script here
+-- how a user was mentioned, a direct mention wins over @channel and @here
+CREATE TYPE mention_kind AS ENUM ('user', 'channel', 'here');
+CREATE TABLE IF NOT EXISTS message_mentions (
+    message_id BIGINT NOT NULL REFERENCES messages(id),
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    kind mention_kind NOT NULL,
+    created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (message_id, user_id)
+);
+CREATE INDEX IF NOT EXISTS message_mentions_user_id_message_id_idx ON message_mentions (user_id, message_id DESC);
+-- notify the mentioned user on its own, regardless of the chat events
+CREATE OR REPLACE FUNCTION notify_message_mentioned() RETURNS TRIGGER AS $$
+DECLARE
+    msg RECORD;
+BEGIN
+    SELECT chat_id, sender_id INTO msg FROM messages WHERE id = NEW.message_id;
+    PERFORM pg_notify(
+        'chat_events',
+        json_build_object(
+            'event', 'mentioned',
+            'chat_id', msg.chat_id,
+            'message_id', NEW.message_id,
+            'sender_id', msg.sender_id,
+            'user_id', NEW.user_id,
+            'kind', NEW.kind
+        )::text
+    );
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+CREATE TRIGGER message_mentioned_trigger
+AFTER INSERT ON message_mentions
+FOR EACH ROW EXECUTE FUNCTION notify_message_mentioned();
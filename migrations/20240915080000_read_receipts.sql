@@ -0,0 +1,26 @@
+-- Add migration script here
+-- read position moved, sent to the reader only so the reader's other devices sync up
+CREATE OR REPLACE FUNCTION notify_message_read() RETURNS TRIGGER AS $$
+BEGIN
+    PERFORM pg_notify(
+        'chat_events',
+        json_build_object(
+            'event', 'message_read',
+            'chat_id', NEW.chat_id,
+            'user_id', NEW.user_id,
+            'message_id', NEW.last_read_message_id
+        )::text
+    );
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+CREATE TRIGGER message_read_trigger
+AFTER UPDATE OF last_read_message_id ON chat_members
+FOR EACH ROW
+WHEN (
+    NEW.last_read_message_id IS NOT NULL
+    AND OLD.last_read_message_id IS DISTINCT FROM NEW.last_read_message_id
+)
+EXECUTE FUNCTION notify_message_read();
+-- unread messages of a member are counted from the read position
+CREATE INDEX IF NOT EXISTS messages_chat_id_id_idx ON messages (chat_id, id);
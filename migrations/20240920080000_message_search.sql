@@ -0,0 +1,32 @@
+-- Add migration script here
+-- full text search over message content
+ALTER TABLE messages
+ADD COLUMN content_tsv tsvector GENERATED ALWAYS AS (to_tsvector('english'::regconfig, content)) STORED;
+CREATE INDEX IF NOT EXISTS messages_content_tsv_idx ON messages USING GIN (content_tsv);
+-- keep the search column out of the notified rows
+CREATE OR REPLACE FUNCTION notify_message_changed() RETURNS TRIGGER AS $$
+DECLARE
+    event TEXT;
+    message JSONB;
+    payload TEXT;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        event := 'new_message';
+    ELSE
+        event := 'message_updated';
+    END IF;
+
+    message := to_jsonb(NEW) - 'content_tsv';
+    payload := json_build_object('event', event, 'message', message)::text;
+    IF octet_length(payload) > 7900 THEN
+        payload := json_build_object(
+            'event', event,
+            'message', message || jsonb_build_object('content', '', 'images', '[]'::jsonb),
+            'truncated', TRUE
+        )::text;
+    END IF;
+
+    PERFORM pg_notify('chat_events', payload);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
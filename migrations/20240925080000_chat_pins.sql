@@ -0,0 +1,40 @@
+-- Add migration script here
+-- how many messages can be pinned in a chat
+ALTER TABLE chats
+ADD COLUMN max_pins INT NOT NULL DEFAULT 50;
+CREATE TABLE IF NOT EXISTS chat_pins (
+    chat_id BIGINT NOT NULL REFERENCES chats(id),
+    message_id BIGINT NOT NULL REFERENCES messages(id),
+    pinned_by BIGINT NOT NULL REFERENCES users(id),
+    pinned_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (chat_id, message_id)
+);
+-- message pinned or unpinned
+CREATE OR REPLACE FUNCTION notify_chat_pin_changed() RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        PERFORM pg_notify(
+            'chat_events',
+            json_build_object(
+                'event', 'message_pinned',
+                'chat_id', NEW.chat_id,
+                'message_id', NEW.message_id,
+                'pinned_by', NEW.pinned_by
+            )::text
+        );
+    ELSE
+        PERFORM pg_notify(
+            'chat_events',
+            json_build_object(
+                'event', 'message_unpinned',
+                'chat_id', OLD.chat_id,
+                'message_id', OLD.message_id
+            )::text
+        );
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+CREATE TRIGGER chat_pin_changed_trigger
+AFTER INSERT OR DELETE ON chat_pins
+FOR EACH ROW EXECUTE FUNCTION notify_chat_pin_changed();
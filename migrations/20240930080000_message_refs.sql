@@ -0,0 +1,6 @@
+-- Add migration script here
+-- a forwarded message points to the original instead of copying its content, and a
+-- message may quote another one of the same chat
+ALTER TABLE messages
+ADD COLUMN forwarded_from BIGINT REFERENCES messages(id),
+ADD COLUMN quoted_id BIGINT REFERENCES messages(id);
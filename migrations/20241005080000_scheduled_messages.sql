@@ -0,0 +1,21 @@
+-- Add migration script here
+-- messages to be sent by the dispatcher at send_at
+CREATE TABLE IF NOT EXISTS scheduled_messages (
+    id BIGSERIAL PRIMARY KEY,
+    chat_id BIGINT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    sender_id BIGINT NOT NULL REFERENCES users(id),
+    content TEXT NOT NULL,
+    images TEXT [] NOT NULL DEFAULT '{}',
+    parent_id BIGINT REFERENCES messages(id),
+    quoted_id BIGINT REFERENCES messages(id),
+    send_at timestamptz NOT NULL,
+    -- the message it was delivered as
+    message_id BIGINT REFERENCES messages(id),
+    -- why it could not be delivered
+    error TEXT,
+    created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+-- pending messages in the order they are due
+CREATE INDEX IF NOT EXISTS scheduled_messages_send_at_idx ON scheduled_messages(send_at)
+WHERE message_id IS NULL AND error IS NULL;
+CREATE INDEX IF NOT EXISTS scheduled_messages_sender_id_idx ON scheduled_messages(sender_id, send_at);
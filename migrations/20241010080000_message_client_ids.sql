@@ -0,0 +1,5 @@
+-- Add migration script here
+-- key a client sends a message with, so a retried send returns the same message
+ALTER TABLE messages
+ADD COLUMN client_msg_id VARCHAR(64);
+CREATE UNIQUE INDEX IF NOT EXISTS messages_client_msg_id_idx ON messages(chat_id, sender_id, client_msg_id);
@@ -0,0 +1,9 @@
+-- Add migration script here
+-- messages are tombstoned by the sweeper once they expire
+ALTER TABLE messages
+ADD COLUMN expires_at timestamptz;
+-- lifetime of new messages in the chat, they never expire when empty
+ALTER TABLE chats
+ADD COLUMN message_ttl_secs BIGINT;
+CREATE INDEX IF NOT EXISTS messages_expires_at_idx ON messages(expires_at)
+WHERE expires_at IS NOT NULL AND deleted_at IS NULL;
@@ -0,0 +1,91 @@
+-- Add migration script here
+-- a poll message asks its content, and is closed for votes after closes_at
+CREATE TABLE IF NOT EXISTS polls (
+    message_id BIGINT PRIMARY KEY REFERENCES messages(id),
+    multi_choice BOOLEAN NOT NULL DEFAULT FALSE,
+    -- results leave out who voted for what
+    anonymous BOOLEAN NOT NULL DEFAULT FALSE,
+    closes_at timestamptz,
+    -- bumped on every vote change, which notifies the results
+    version BIGINT NOT NULL DEFAULT 0,
+    created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+CREATE TABLE IF NOT EXISTS poll_options (
+    id BIGSERIAL PRIMARY KEY,
+    message_id BIGINT NOT NULL REFERENCES polls(message_id),
+    position SMALLINT NOT NULL,
+    text VARCHAR(256) NOT NULL,
+    UNIQUE (message_id, position)
+);
+-- one row per user and chosen option of a poll
+CREATE TABLE IF NOT EXISTS poll_votes (
+    message_id BIGINT NOT NULL REFERENCES polls(message_id),
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    option_id BIGINT NOT NULL REFERENCES poll_options(id),
+    created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (message_id, user_id, option_id)
+);
+CREATE INDEX IF NOT EXISTS poll_votes_option_id_idx ON poll_votes(option_id);
+-- options with their results, NULL when the message is not a poll
+CREATE OR REPLACE FUNCTION poll_json(poll_message_id BIGINT) RETURNS jsonb AS $$
+    SELECT jsonb_build_object(
+        'multi_choice', p.multi_choice,
+        'anonymous', p.anonymous,
+        'closes_at', p.closes_at,
+        'voter_count', (
+            SELECT COUNT(DISTINCT v.user_id) FROM poll_votes v WHERE v.message_id = p.message_id
+        ),
+        'options', COALESCE((
+            SELECT jsonb_agg(jsonb_build_object(
+                'id', o.id,
+                'text', o.text,
+                'votes', (SELECT COUNT(*) FROM poll_votes v WHERE v.option_id = o.id),
+                'voters', CASE WHEN p.anonymous THEN '[]'::jsonb ELSE COALESCE((
+                    SELECT jsonb_agg(v.user_id ORDER BY v.created_at, v.user_id)
+                    FROM poll_votes v WHERE v.option_id = o.id
+                ), '[]'::jsonb) END
+            ) ORDER BY o.position)
+            FROM poll_options o WHERE o.message_id = p.message_id
+        ), '[]'::jsonb)
+    )
+    FROM polls p WHERE p.message_id = poll_message_id
+$$ LANGUAGE sql STABLE;
+-- poll created or voted on, voters are dropped when the payload is over the pg_notify
+-- limit (8000 bytes)
+CREATE OR REPLACE FUNCTION notify_poll_changed() RETURNS TRIGGER AS $$
+DECLARE
+    chat BIGINT;
+    poll jsonb;
+    payload TEXT;
+BEGIN
+    SELECT chat_id INTO chat FROM messages WHERE id = NEW.message_id;
+    poll := poll_json(NEW.message_id);
+    payload := json_build_object(
+        'event', 'poll_updated',
+        'chat_id', chat,
+        'message_id', NEW.message_id,
+        'poll', poll
+    )::text;
+    IF octet_length(payload) > 7900 THEN
+        poll := poll || jsonb_build_object('options', (
+            SELECT jsonb_agg(o || jsonb_build_object('voters', '[]'::jsonb))
+            FROM jsonb_array_elements(poll -> 'options') o
+        ));
+        payload := json_build_object(
+            'event', 'poll_updated',
+            'chat_id', chat,
+            'message_id', NEW.message_id,
+            'poll', poll,
+            'truncated', TRUE
+        )::text;
+    END IF;
+
+    PERFORM pg_notify('chat_events', payload);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+-- deferred to the commit, so options and votes of the transaction are in the results
+CREATE CONSTRAINT TRIGGER poll_changed_trigger
+AFTER INSERT OR UPDATE OF version ON polls
+DEFERRABLE INITIALLY DEFERRED
+FOR EACH ROW EXECUTE FUNCTION notify_poll_changed();
@@ -0,0 +1,56 @@
+-- Add migration script here
+-- set by the /topic command
+ALTER TABLE chats
+ADD COLUMN topic VARCHAR(250);
+-- topic changed
+CREATE OR REPLACE FUNCTION notify_chat_topic_changed() RETURNS TRIGGER AS $$
+BEGIN
+    PERFORM pg_notify(
+        'chat_events',
+        json_build_object(
+            'event', 'topic_changed',
+            'chat_id', NEW.id,
+            'topic', NEW.topic
+        )::text
+    );
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+CREATE TRIGGER chat_topic_changed_trigger
+AFTER UPDATE OF topic ON chats
+FOR EACH ROW
+WHEN (OLD.topic IS DISTINCT FROM NEW.topic)
+EXECUTE FUNCTION notify_chat_topic_changed();
+-- set by the /remind command, sent to the user only once remind_at has passed
+CREATE TABLE IF NOT EXISTS reminders (
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    chat_id BIGINT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    text TEXT NOT NULL,
+    remind_at timestamptz NOT NULL,
+    sent_at timestamptz,
+    created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX IF NOT EXISTS reminders_remind_at_idx ON reminders(remind_at)
+WHERE sent_at IS NULL;
+-- reminder due
+CREATE OR REPLACE FUNCTION notify_reminder_sent() RETURNS TRIGGER AS $$
+BEGIN
+    PERFORM pg_notify(
+        'chat_events',
+        json_build_object(
+            'event', 'reminder',
+            'chat_id', NEW.chat_id,
+            'user_id', NEW.user_id,
+            'reminder_id', NEW.id,
+            'text', NEW.text
+        )::text
+    );
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+CREATE TRIGGER reminder_sent_trigger
+AFTER UPDATE OF sent_at ON reminders
+FOR EACH ROW
+WHEN (OLD.sent_at IS NULL AND NEW.sent_at IS NOT NULL)
+EXECUTE FUNCTION notify_reminder_sent();
@@ -0,0 +1,5 @@
+-- Add migration script here
+-- client message id of the /remind command, so a retried command creates the reminder once
+ALTER TABLE reminders
+ADD COLUMN client_msg_id VARCHAR(64);
+CREATE UNIQUE INDEX IF NOT EXISTS reminders_client_msg_id_idx ON reminders(user_id, chat_id, client_msg_id);
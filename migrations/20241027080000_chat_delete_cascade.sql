@@ -0,0 +1,52 @@
+-- Add migration script here
+-- deleting a chat deletes its messages and everything hanging off them, messages of
+-- other chats only lose their reference
+ALTER TABLE messages
+DROP CONSTRAINT messages_chat_id_fkey,
+ADD CONSTRAINT messages_chat_id_fkey FOREIGN KEY (chat_id) REFERENCES chats(id) ON DELETE CASCADE,
+DROP CONSTRAINT messages_parent_id_fkey,
+ADD CONSTRAINT messages_parent_id_fkey FOREIGN KEY (parent_id) REFERENCES messages(id) ON DELETE CASCADE,
+DROP CONSTRAINT messages_forwarded_from_fkey,
+ADD CONSTRAINT messages_forwarded_from_fkey FOREIGN KEY (forwarded_from) REFERENCES messages(id) ON DELETE SET NULL,
+DROP CONSTRAINT messages_quoted_id_fkey,
+ADD CONSTRAINT messages_quoted_id_fkey FOREIGN KEY (quoted_id) REFERENCES messages(id) ON DELETE SET NULL;
+
+ALTER TABLE message_edits
+DROP CONSTRAINT message_edits_message_id_fkey,
+ADD CONSTRAINT message_edits_message_id_fkey FOREIGN KEY (message_id) REFERENCES messages(id) ON DELETE CASCADE;
+
+ALTER TABLE message_reactions
+DROP CONSTRAINT message_reactions_message_id_fkey,
+ADD CONSTRAINT message_reactions_message_id_fkey FOREIGN KEY (message_id) REFERENCES messages(id) ON DELETE CASCADE;
+
+ALTER TABLE message_mentions
+DROP CONSTRAINT message_mentions_message_id_fkey,
+ADD CONSTRAINT message_mentions_message_id_fkey FOREIGN KEY (message_id) REFERENCES messages(id) ON DELETE CASCADE;
+
+ALTER TABLE chat_pins
+DROP CONSTRAINT chat_pins_chat_id_fkey,
+ADD CONSTRAINT chat_pins_chat_id_fkey FOREIGN KEY (chat_id) REFERENCES chats(id) ON DELETE CASCADE,
+DROP CONSTRAINT chat_pins_message_id_fkey,
+ADD CONSTRAINT chat_pins_message_id_fkey FOREIGN KEY (message_id) REFERENCES messages(id) ON DELETE CASCADE;
+
+ALTER TABLE scheduled_messages
+DROP CONSTRAINT scheduled_messages_parent_id_fkey,
+ADD CONSTRAINT scheduled_messages_parent_id_fkey FOREIGN KEY (parent_id) REFERENCES messages(id) ON DELETE CASCADE,
+DROP CONSTRAINT scheduled_messages_quoted_id_fkey,
+ADD CONSTRAINT scheduled_messages_quoted_id_fkey FOREIGN KEY (quoted_id) REFERENCES messages(id) ON DELETE SET NULL,
+DROP CONSTRAINT scheduled_messages_message_id_fkey,
+ADD CONSTRAINT scheduled_messages_message_id_fkey FOREIGN KEY (message_id) REFERENCES messages(id) ON DELETE SET NULL;
+
+ALTER TABLE polls
+DROP CONSTRAINT polls_message_id_fkey,
+ADD CONSTRAINT polls_message_id_fkey FOREIGN KEY (message_id) REFERENCES messages(id) ON DELETE CASCADE;
+
+ALTER TABLE poll_options
+DROP CONSTRAINT poll_options_message_id_fkey,
+ADD CONSTRAINT poll_options_message_id_fkey FOREIGN KEY (message_id) REFERENCES polls(message_id) ON DELETE CASCADE;
+
+ALTER TABLE poll_votes
+DROP CONSTRAINT poll_votes_message_id_fkey,
+ADD CONSTRAINT poll_votes_message_id_fkey FOREIGN KEY (message_id) REFERENCES polls(message_id) ON DELETE CASCADE,
+DROP CONSTRAINT poll_votes_option_id_fkey,
+ADD CONSTRAINT poll_votes_option_id_fkey FOREIGN KEY (option_id) REFERENCES poll_options(id) ON DELETE CASCADE;
@@ -0,0 +1,105 @@
+use axum::{
+    extract::{FromRequestParts, Query, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::AppState;
+
+#[derive(Debug, Deserialize)]
+struct TokenParams {
+    token: String,
+}
+
+/// Verify the token signed by chat_server. Browsers can not set headers on an
+/// EventSource, so the token may also be passed as the `token` query parameter.
+pub async fn verify_token(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let (mut parts, body) = req.into_parts();
+    let token =
+        match TypedHeader::<Authorization<Bearer>>::from_request_parts(&mut parts, &state).await {
+            Ok(TypedHeader(Authorization(bearer))) => bearer.token().to_string(),
+            Err(e) => match Query::<TokenParams>::from_request_parts(&mut parts, &state).await {
+                Ok(Query(params)) => params.token,
+                Err(_) => {
+                    let msg = format!("failed to parse Authorization header: {}", e);
+                    warn!(msg);
+                    return (StatusCode::UNAUTHORIZED, msg).into_response();
+                }
+            },
+        };
+
+    let req = match state.dk.verify(&token) {
+        Ok(user) => {
+            let mut req = Request::from_parts(parts, body);
+            req.extensions_mut().insert(user);
+            req
+        }
+        Err(e) => {
+            let msg = format!("failed to verify token: {}", e);
+            warn!(msg);
+            return (StatusCode::FORBIDDEN, msg).into_response();
+        }
+    };
+
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use axum::{
+        body::Body, extract::Request, http::StatusCode, middleware::from_fn_with_state,
+        response::IntoResponse, routing::get, Extension, Router,
+    };
+    use chat_core::{EncodingKey, User};
+    use tower::ServiceExt;
+
+    use super::verify_token;
+    use crate::{AppConfig, AppState};
+
+    async fn handler(Extension(user): Extension<User>) -> impl IntoResponse {
+        (StatusCode::OK, user.email)
+    }
+
+    #[tokio::test]
+    async fn verify_token_middleware_should_work() -> Result<()> {
+        let state = AppState::new_for_test(AppConfig::load()?)?;
+        let ek = EncodingKey::load(include_str!("../fixtures/encoding.pem"))?;
+        let token = ek.sign(User::new(1, "test_user", "test_user@acme.org"))?;
+
+        let app = Router::new()
+            .route("/", get(handler))
+            .layer(from_fn_with_state(state.clone(), verify_token))
+            .with_state(state);
+
+        let req = Request::builder()
+            .uri("/")
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let req = Request::builder()
+            .uri(format!("/?token={}", token))
+            .body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let req = Request::builder().uri("/").body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let req = Request::builder().uri("/?token=bad").body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+}
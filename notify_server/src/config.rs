@@ -0,0 +1,28 @@
+use anyhow::Result;
+use chat_core::{load_config, ServerConfig};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AppConfig {
+    pub server: ServerConfig,
+    pub auth: AuthConfig,
+    pub replay: ReplayConfig,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuthConfig {
+    pub pk: String,
+}
+
+/// Events kept for each user, so reconnecting clients can catch up.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReplayConfig {
+    pub buffer_size: usize,
+    pub retention_secs: u64,
+}
+
+impl AppConfig {
+    pub fn load() -> Result<Self> {
+        load_config("notify.yml", "NOTIFY_CONFIG")
+    }
+}
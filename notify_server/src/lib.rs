@@ -1,20 +1,135 @@
+mod auth;
+mod config;
+mod notif;
+mod registry;
 mod sse;
+mod typing;
+mod ws;
 
+use std::{fmt, ops::Deref, sync::Arc, time::Duration};
+
+use anyhow::{Context, Result};
 use axum::{
+    middleware::from_fn_with_state,
     response::{Html, IntoResponse},
-    routing::get,
+    routing::{get, post},
     Router,
 };
+use chat_core::DecodingKey;
+use sqlx::PgPool;
 use sse::sse_handler;
+use typing::typing_handler;
+use ws::ws_handler;
+
+pub use auth::verify_token;
+pub use config::{AppConfig, ReplayConfig};
+pub use notif::setup_pg_listener;
+pub use registry::{Subscription, UserEvent, UserRegistry};
+pub use typing::TypingRegistry;
+pub use ws::ClientFrame;
 
 const INDEX_HTML: &str = include_str!("../index.html");
 
-pub fn get_router() -> Router {
-    Router::new()
-        .route("/", get(index_handler))
+#[derive(Debug, Clone)]
+pub struct AppState(Arc<AppStateInner>);
+
+pub struct AppStateInner {
+    pub config: AppConfig,
+    pub dk: DecodingKey,
+    pub pool: PgPool,
+    pub users: UserRegistry,
+    pub typing: TypingRegistry,
+}
+
+pub async fn get_router(config: AppConfig) -> Result<Router> {
+    let state = AppState::try_new(config).await?;
+    setup_pg_listener(state.clone()).await?;
+    spawn_replay_pruner(state.clone());
+    spawn_typing_expirer(state.clone());
+
+    let app = Router::new()
         .route("/events", get(sse_handler))
+        .route("/ws", get(ws_handler))
+        .route("/chats/:id/typing", post(typing_handler))
+        .layer(from_fn_with_state(state.clone(), verify_token))
+        .route("/", get(index_handler))
+        .with_state(state);
+
+    Ok(app)
+}
+
+// expired events of users who are not connected are never touched otherwise
+fn spawn_replay_pruner(state: AppState) {
+    let period = Duration::from_secs(state.config.replay.retention_secs.max(1));
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            state.users.prune();
+        }
+    });
+}
+
+fn spawn_typing_expirer(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            state.expire_typing();
+        }
+    });
 }
 
 async fn index_handler() -> impl IntoResponse {
     Html(INDEX_HTML)
 }
+
+impl Deref for AppState {
+    type Target = AppStateInner;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl AppState {
+    pub async fn try_new(config: AppConfig) -> Result<Self> {
+        let dk = DecodingKey::load(&config.auth.pk).context("load pk failed")?;
+        let pool = PgPool::connect(&config.server.db_url)
+            .await
+            .context("connect to db failed")?;
+        let users = UserRegistry::new(&config.replay);
+        Ok(Self(Arc::new(AppStateInner {
+            config,
+            dk,
+            pool,
+            users,
+            typing: TypingRegistry::default(),
+        })))
+    }
+}
+
+impl fmt::Debug for AppStateInner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AppStateInner")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+impl AppState {
+    // the pool connects on first use, so tests without database access can run anywhere
+    pub fn new_for_test(config: AppConfig) -> Result<Self> {
+        let dk = DecodingKey::load(&config.auth.pk)?;
+        let pool = PgPool::connect_lazy(&config.server.db_url)?;
+        let users = UserRegistry::new(&config.replay);
+        Ok(Self(Arc::new(AppStateInner {
+            config,
+            dk,
+            pool,
+            users,
+            typing: TypingRegistry::default(),
+        })))
+    }
+}
@@ -1,5 +1,5 @@
 use anyhow::Result;
-use notify_server::get_router;
+use notify_server::{get_router, AppConfig};
 use tokio::net::TcpListener;
 use tracing::info;
 use tracing_subscriber::{
@@ -11,9 +11,10 @@ async fn main() -> Result<()> {
     let layer = Layer::new().pretty().with_filter(LevelFilter::INFO);
     tracing_subscriber::registry().with(layer).init();
 
-    let addr = "0.0.0.0:6687";
+    let config = AppConfig::load()?;
+    let addr = format!("0.0.0.0:{}", config.server.port);
 
-    let app = get_router();
+    let app = get_router(config).await?;
     let listener = TcpListener::bind(&addr).await?;
 
     axum::serve(listener, app.into_make_service()).await?;
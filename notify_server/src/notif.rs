@@ -0,0 +1,164 @@
+use std::{collections::HashSet, sync::Arc};
+
+use anyhow::Result;
+use chat_core::{AppEvent, MentionKind, Message};
+use futures::StreamExt;
+use sqlx::{postgres::PgListener, PgPool};
+use tracing::{info, warn};
+
+use crate::AppState;
+
+const CHAT_EVENTS_CHANNEL: &str = "chat_events";
+
+/// Users who should receive the event: the current members of the chat, and the
+/// member who was just removed from it. Mentions, reminders and read positions only go
+/// to the user they are for.
+pub async fn receivers(event: &AppEvent, pool: &PgPool) -> Result<HashSet<i64>> {
+    if let AppEvent::Mentioned { user_id, .. }
+    | AppEvent::Reminder { user_id, .. }
+    | AppEvent::MessageRead { user_id, .. } = event
+    {
+        return Ok(HashSet::from([*user_id]));
+    }
+
+    let Some(chat_id) = event.chat_id() else {
+        return Ok(HashSet::new());
+    };
+    let mut users = chat_members(chat_id, pool).await?;
+    if let AppEvent::MemberRemoved { user_id, .. } = event {
+        users.insert(*user_id);
+    }
+
+    Ok(users)
+}
+
+/// Followers of the thread a new reply was posted to: the author of the thread and
+/// everyone who replied in it, who are still members of the chat, except the sender.
+pub async fn thread_followers(message: &Message, pool: &PgPool) -> Result<HashSet<i64>> {
+    let Some(parent_id) = message.parent_id else {
+        return Ok(HashSet::new());
+    };
+    let users = sqlx::query_scalar(
+        r#"
+        SELECT DISTINCT m.sender_id
+        FROM messages m
+        JOIN chat_members cm ON cm.chat_id = m.chat_id AND cm.user_id = m.sender_id
+        WHERE (m.id = $1 OR m.parent_id = $1) AND m.sender_id <> $2
+        "#,
+    )
+    .bind(parent_id)
+    .bind(message.sender_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(users.into_iter().collect())
+}
+
+pub async fn chat_members(chat_id: i64, pool: &PgPool) -> Result<HashSet<i64>> {
+    let users = sqlx::query_scalar("SELECT user_id FROM chat_members WHERE chat_id = $1")
+        .bind(chat_id)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(users.into_iter().collect())
+}
+
+pub async fn setup_pg_listener(state: AppState) -> Result<()> {
+    let mut listener = PgListener::connect(&state.config.server.db_url).await?;
+    listener.listen(CHAT_EVENTS_CHANNEL).await?;
+    info!("Listening to channel: {}", CHAT_EVENTS_CHANNEL);
+
+    let mut stream = listener.into_stream();
+    tokio::spawn(async move {
+        while let Some(notification) = stream.next().await {
+            let notification = match notification {
+                Ok(notification) => notification,
+                Err(e) => {
+                    warn!("failed to receive notification: {}", e);
+                    continue;
+                }
+            };
+
+            let event = match serde_json::from_str::<AppEvent>(notification.payload()) {
+                Ok(event) => Arc::new(event),
+                Err(e) => {
+                    warn!(
+                        "failed to parse notification {}: {}",
+                        notification.payload(),
+                        e
+                    );
+                    continue;
+                }
+            };
+            match receivers(&event, &state.pool).await {
+                Ok(mut users) => {
+                    // @here only reaches users who are connected right now
+                    if let AppEvent::Mentioned {
+                        kind: MentionKind::Here,
+                        ..
+                    } = event.as_ref()
+                    {
+                        users.retain(|id| state.users.connections(*id) > 0);
+                    }
+                    state.users.publish(users, event.clone());
+                }
+                Err(e) => warn!("failed to get receivers of {}: {}", event.name(), e),
+            }
+
+            if let AppEvent::NewMessage { message, .. } = event.as_ref() {
+                state.stop_typing(message.chat_id, message.sender_id);
+                if let Err(e) = notify_thread_followers(message, &state).await {
+                    warn!(
+                        "failed to notify followers of message {}: {}",
+                        message.id, e
+                    );
+                }
+            }
+            if let AppEvent::MemberRemoved { chat_id, user_id } = event.as_ref() {
+                state.remove_typing_member(*chat_id, *user_id);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+async fn notify_thread_followers(message: &Message, state: &AppState) -> Result<()> {
+    let Some(parent_id) = message.parent_id else {
+        return Ok(());
+    };
+    let followers = thread_followers(message, &state.pool).await?;
+    if followers.is_empty() {
+        return Ok(());
+    }
+
+    let event = AppEvent::ThreadReply {
+        chat_id: message.chat_id,
+        parent_id,
+        message_id: message.id,
+        sender_id: message.sender_id,
+    };
+    state.users.publish(followers, Arc::new(event));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AppConfig;
+
+    #[tokio::test]
+    async fn read_position_should_only_go_to_the_reader() -> Result<()> {
+        let state = AppState::new_for_test(AppConfig::load()?)?;
+        let event = AppEvent::MessageRead {
+            chat_id: 1,
+            user_id: 2,
+            message_id: 10,
+        };
+        // the other members of chat 1 are left out without asking the database
+        let users = receivers(&event, &state.pool).await?;
+        assert_eq!(users, HashSet::from([2]));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,380 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use chat_core::AppEvent;
+use chrono::Utc;
+use dashmap::{mapref::entry::Entry, DashMap};
+use futures::Stream;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::warn;
+
+use crate::config::ReplayConfig;
+
+const USER_CHANNEL_CAPACITY: usize = 256;
+
+/// An event with the id sent to clients, which they send back as `Last-Event-ID`.
+#[derive(Debug, PartialEq)]
+pub struct UserEvent {
+    pub id: u64,
+    pub event: Arc<AppEvent>,
+    created_at: Instant,
+}
+
+#[derive(Debug, Default)]
+struct UserEntry {
+    conns: HashMap<u64, mpsc::Sender<Arc<UserEvent>>>,
+    // recent events of the user, for clients to catch up after reconnecting
+    buffer: VecDeque<Arc<UserEvent>>,
+    // id of the newest event dropped from the buffer, clients behind it missed events
+    evicted_through: u64,
+}
+
+/// Connected streams and recent events of each user. A user may be connected from
+/// several clients, every connection gets its own channel.
+#[derive(Debug)]
+pub struct UserRegistry {
+    users: Arc<DashMap<i64, UserEntry>>,
+    next_conn_id: AtomicU64,
+    next_event_id: AtomicU64,
+    // ids before it were sent before a restart, and are gone
+    first_event_id: u64,
+    // newest event dropped with a pruned user, new entries assume they missed it
+    pruned_through: AtomicU64,
+    buffer_size: usize,
+    retention: Duration,
+}
+
+/// Events of one connection: the missed events first, then the live ones. The
+/// connection is unregistered when it is dropped.
+#[derive(Debug)]
+pub struct Subscription {
+    id: u64,
+    user_id: i64,
+    replay: VecDeque<Arc<UserEvent>>,
+    rx: mpsc::Receiver<Arc<UserEvent>>,
+    users: Arc<DashMap<i64, UserEntry>>,
+}
+
+impl UserRegistry {
+    pub fn new(config: &ReplayConfig) -> Self {
+        // start from the current time, so ids keep growing across restarts
+        let first_event_id = Utc::now().timestamp_micros() as u64;
+        Self {
+            users: Default::default(),
+            next_conn_id: AtomicU64::new(0),
+            next_event_id: AtomicU64::new(first_event_id),
+            first_event_id,
+            pruned_through: AtomicU64::new(0),
+            buffer_size: config.buffer_size,
+            retention: Duration::from_secs(config.retention_secs),
+        }
+    }
+
+    /// Register a connection of a user. Buffered events after `last_event_id` are
+    /// replayed before live events, after a `resync` event when some of the missed
+    /// events are not buffered anymore.
+    pub fn subscribe(&self, user_id: i64, last_event_id: Option<u64>) -> Subscription {
+        let id = self.next_conn_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = mpsc::channel(USER_CHANNEL_CAPACITY);
+
+        let mut entry = self.users.entry(user_id).or_insert_with(|| UserEntry {
+            evicted_through: self.pruned_through.load(Ordering::Relaxed),
+            ..Default::default()
+        });
+        self.prune_buffer(&mut entry);
+        let replay = match last_event_id {
+            Some(last_event_id) => {
+                let mut replay: VecDeque<_> = entry
+                    .buffer
+                    .iter()
+                    .filter(|e| e.id > last_event_id)
+                    .cloned()
+                    .collect();
+                if last_event_id < self.first_event_id || last_event_id < entry.evicted_through {
+                    // keeps the id, the client is no further than before
+                    replay.push_front(Arc::new(UserEvent {
+                        id: last_event_id,
+                        event: Arc::new(AppEvent::Resync),
+                        created_at: Instant::now(),
+                    }));
+                }
+                replay
+            }
+            None => VecDeque::new(),
+        };
+        entry.conns.insert(id, tx);
+
+        Subscription {
+            id,
+            user_id,
+            replay,
+            rx,
+            users: self.users.clone(),
+        }
+    }
+
+    /// Assign the event an id, buffer it unless it is ephemeral, and send it to all
+    /// connections of the users.
+    /// A connection which can not keep up is closed, so its client reconnects and
+    /// catches up from the buffer.
+    pub fn publish(&self, users: impl IntoIterator<Item = i64>, event: Arc<AppEvent>) -> u64 {
+        let event = Arc::new(UserEvent {
+            id: self.next_event_id.fetch_add(1, Ordering::Relaxed),
+            event,
+            created_at: Instant::now(),
+        });
+
+        for user_id in users {
+            let mut entry = if event.event.is_ephemeral() {
+                // only for users online right now
+                match self.users.get_mut(&user_id) {
+                    Some(entry) => entry,
+                    None => continue,
+                }
+            } else {
+                let mut entry = self.users.entry(user_id).or_insert_with(|| UserEntry {
+                    evicted_through: self.pruned_through.load(Ordering::Relaxed),
+                    ..Default::default()
+                });
+                entry.buffer.push_back(event.clone());
+                self.prune_buffer(&mut entry);
+                entry
+            };
+
+            entry
+                .conns
+                .retain(|id, tx| match tx.try_send(event.clone()) {
+                    Ok(()) => true,
+                    Err(TrySendError::Full(_)) => {
+                        warn!("connection {} of user {} is lagging, closed", id, user_id);
+                        false
+                    }
+                    Err(TrySendError::Closed(_)) => false,
+                });
+        }
+
+        event.id
+    }
+
+    /// Drop expired events, and users with neither events nor connections.
+    pub fn prune(&self) {
+        self.users.retain(|_, entry| {
+            self.prune_buffer(entry);
+            if !entry.conns.is_empty() || !entry.buffer.is_empty() {
+                return true;
+            }
+            self.pruned_through
+                .fetch_max(entry.evicted_through, Ordering::Relaxed);
+            false
+        });
+    }
+
+    pub fn connections(&self, user_id: i64) -> usize {
+        self.users.get(&user_id).map(|e| e.conns.len()).unwrap_or(0)
+    }
+
+    fn prune_buffer(&self, entry: &mut UserEntry) {
+        let now = Instant::now();
+        while entry.buffer.len() > self.buffer_size
+            || entry
+                .buffer
+                .front()
+                .is_some_and(|e| now - e.created_at > self.retention)
+        {
+            if let Some(event) = entry.buffer.pop_front() {
+                entry.evicted_through = entry.evicted_through.max(event.id);
+            }
+        }
+    }
+}
+
+impl Stream for Subscription {
+    type Item = Arc<UserEvent>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Some(event) = self.replay.pop_front() {
+            return Poll::Ready(Some(event));
+        }
+        self.rx.poll_recv(cx)
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        if let Entry::Occupied(mut entry) = self.users.entry(self.user_id) {
+            entry.get_mut().conns.remove(&self.id);
+            // users who missed events are removed by prune, which remembers it
+            let entry_ref = entry.get();
+            if entry_ref.conns.is_empty()
+                && entry_ref.buffer.is_empty()
+                && entry_ref.evicted_through == 0
+            {
+                entry.remove();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+impl UserEvent {
+    pub fn new(id: u64, event: AppEvent) -> Self {
+        Self {
+            id,
+            event: Arc::new(event),
+            created_at: Instant::now(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use futures::StreamExt;
+
+    fn registry(buffer_size: usize) -> UserRegistry {
+        UserRegistry::new(&ReplayConfig {
+            buffer_size,
+            retention_secs: 60,
+        })
+    }
+
+    fn event(chat_id: i64, user_id: i64) -> Arc<AppEvent> {
+        Arc::new(AppEvent::MemberAdded { chat_id, user_id })
+    }
+
+    #[tokio::test]
+    async fn registry_should_route_events_to_user_connections() -> Result<()> {
+        let registry = registry(10);
+        let mut sub1 = registry.subscribe(1, None);
+        let mut sub2 = registry.subscribe(1, None);
+        let mut sub3 = registry.subscribe(2, None);
+        assert_eq!(registry.connections(1), 2);
+
+        let id1 = registry.publish([1], event(1, 5));
+        let id2 = registry.publish([1, 2], event(2, 5));
+        assert!(id2 > id1);
+
+        assert_eq!(sub1.next().await.unwrap().id, id1);
+        assert_eq!(sub1.next().await.unwrap().id, id2);
+        assert_eq!(sub2.next().await.unwrap().event, event(1, 5));
+        assert_eq!(sub3.next().await.unwrap().id, id2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn subscription_should_replay_missed_events() -> Result<()> {
+        let registry = registry(2);
+        let sub = registry.subscribe(1, None);
+        let id1 = registry.publish([1], event(1, 5));
+        drop(sub);
+        assert_eq!(registry.connections(1), 0);
+
+        // sent while offline, the first one is evicted from the buffer
+        registry.publish([1], event(2, 5));
+        let id3 = registry.publish([1], event(3, 5));
+        let id4 = registry.publish([1], event(4, 5));
+
+        let mut sub = registry.subscribe(1, Some(id1));
+        let resync = sub.next().await.unwrap();
+        assert_eq!(*resync.event, AppEvent::Resync);
+        assert_eq!(resync.id, id1);
+        assert_eq!(sub.next().await.unwrap().id, id3);
+        assert_eq!(sub.next().await.unwrap().id, id4);
+
+        let id5 = registry.publish([1], event(5, 5));
+        assert_eq!(sub.next().await.unwrap().id, id5);
+
+        let mut sub = registry.subscribe(1, Some(id5));
+        let id6 = registry.publish([1], event(6, 5));
+        assert_eq!(sub.next().await.unwrap().id, id6);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ephemeral_events_should_not_be_replayed() -> Result<()> {
+        let registry = registry(10);
+        let mut sub = registry.subscribe(1, None);
+        let typing = Arc::new(AppEvent::Typing {
+            chat_id: 1,
+            user_id: 2,
+        });
+        let id1 = registry.publish([1, 3], typing.clone());
+        assert_eq!(sub.next().await.unwrap().event, typing);
+        drop(sub);
+        assert!(registry.users.is_empty());
+
+        let id2 = registry.publish([1], event(1, 5));
+        let mut sub = registry.subscribe(1, Some(id1));
+        assert_eq!(sub.next().await.unwrap().id, id2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn missed_events_should_resync() -> Result<()> {
+        let registry = UserRegistry::new(&ReplayConfig {
+            buffer_size: 10,
+            retention_secs: 0,
+        });
+        // ids of the previous run of the server
+        let mut sub = registry.subscribe(1, Some(registry.first_event_id - 1));
+        assert_eq!(*sub.next().await.unwrap().event, AppEvent::Resync);
+        drop(sub);
+
+        // expired and pruned with the user while offline
+        let id1 = registry.publish([2], event(1, 5));
+        let id2 = registry.publish([2], event(2, 5));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        registry.prune();
+        assert!(registry.users.is_empty());
+        let mut sub = registry.subscribe(2, Some(id1));
+        assert_eq!(*sub.next().await.unwrap().event, AppEvent::Resync);
+
+        // nothing was missed after the last event
+        let mut sub = registry.subscribe(2, Some(id2));
+        let id3 = registry.publish([2], event(3, 5));
+        assert_eq!(sub.next().await.unwrap().id, id3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dropped_subscription_should_be_removed() -> Result<()> {
+        let registry = registry(10);
+        let sub1 = registry.subscribe(1, None);
+        let sub2 = registry.subscribe(1, None);
+
+        drop(sub1);
+        assert_eq!(registry.connections(1), 1);
+        drop(sub2);
+        assert_eq!(registry.connections(1), 0);
+        assert!(registry.users.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn expired_events_should_be_pruned() -> Result<()> {
+        let registry = UserRegistry::new(&ReplayConfig {
+            buffer_size: 10,
+            retention_secs: 0,
+        });
+        registry.publish([1, 2], event(1, 5));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        registry.prune();
+        assert!(registry.users.is_empty());
+
+        Ok(())
+    }
+}
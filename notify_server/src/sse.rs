@@ -1,19 +1,54 @@
 use std::{convert::Infallible, time::Duration};
 
-use axum::response::{sse::Event, Sse};
+use axum::{
+    extract::State,
+    http::HeaderMap,
+    response::{sse::Event, Sse},
+    Extension,
+};
 use axum_extra::{headers, TypedHeader};
-use futures::{stream, Stream};
+use chat_core::User;
+use futures::Stream;
 use tokio_stream::StreamExt;
-use tracing::info;
+use tracing::{info, warn};
+
+use crate::AppState;
+
+const LAST_EVENT_ID: &str = "last-event-id";
 
 pub(crate) async fn sse_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
     TypedHeader(user_agent): TypedHeader<headers::UserAgent>,
+    headers: HeaderMap,
 ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
-    info!("User-Agent: {}", user_agent);
+    // sent by EventSource when it reconnects
+    let last_event_id = headers
+        .get(LAST_EVENT_ID)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    info!(
+        "User {} connected, User-Agent: {}, Last-Event-ID: {:?}",
+        user.id, user_agent, last_event_id
+    );
 
-    let stream = stream::repeat_with(|| Event::default().data("hi!"))
-        .map(Ok)
-        .throttle(Duration::from_secs(1));
+    // the subscription is removed from the registry when the client disconnects
+    let stream = state
+        .users
+        .subscribe(user.id, last_event_id)
+        .filter_map(|event| {
+            match Event::default()
+                .id(event.id.to_string())
+                .event(event.event.name())
+                .json_data(event.event.as_ref())
+            {
+                Ok(event) => Some(Ok(event)),
+                Err(e) => {
+                    warn!("failed to serialize event: {}", e);
+                    None
+                }
+            }
+        });
 
     Sse::new(stream).keep_alive(
         axum::response::sse::KeepAlive::new()
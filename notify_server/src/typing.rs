@@ -0,0 +1,253 @@
+use std::{
+    collections::HashSet,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension,
+};
+use chat_core::{AppEvent, User};
+use dashmap::DashMap;
+use tracing::warn;
+
+use crate::{notif::chat_members, AppState};
+
+// a user typing along is announced again at most this often
+const TYPING_THROTTLE: Duration = Duration::from_secs(3);
+// typing stops when it is not reported for this long
+const TYPING_TIMEOUT: Duration = Duration::from_secs(6);
+
+/// Users typing in chats, only kept in memory.
+#[derive(Debug, Default)]
+pub struct TypingRegistry {
+    typing: DashMap<(i64, i64), Typing>,
+}
+
+#[derive(Debug)]
+struct Typing {
+    // who was told, so they are told when it stops
+    members: HashSet<i64>,
+    published_at: Instant,
+    expires_at: Instant,
+}
+
+impl TypingRegistry {
+    /// Keep the user typing, true when it was announced too recently to do it again.
+    pub fn throttle(&self, chat_id: i64, user_id: i64, now: Instant) -> bool {
+        match self.typing.get_mut(&(chat_id, user_id)) {
+            Some(mut typing) if now < typing.published_at + TYPING_THROTTLE => {
+                typing.expires_at = now + TYPING_TIMEOUT;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Record that the user's typing was announced to the members.
+    pub fn start(&self, chat_id: i64, user_id: i64, members: HashSet<i64>, now: Instant) {
+        self.typing.insert(
+            (chat_id, user_id),
+            Typing {
+                members,
+                published_at: now,
+                expires_at: now + TYPING_TIMEOUT,
+            },
+        );
+    }
+
+    /// Stop the user typing, and return the members to tell.
+    pub fn stop(&self, chat_id: i64, user_id: i64) -> Option<HashSet<i64>> {
+        self.typing
+            .remove(&(chat_id, user_id))
+            .map(|(_, typing)| typing.members)
+    }
+
+    /// Stop telling a user who left the chat about typing in it.
+    pub fn remove_member(&self, chat_id: i64, user_id: i64) {
+        for mut typing in self.typing.iter_mut() {
+            if typing.key().0 == chat_id {
+                typing.members.remove(&user_id);
+            }
+        }
+    }
+
+    /// Remove typing which was not reported in time, with the members to tell.
+    pub fn expire(&self, now: Instant) -> Vec<(i64, i64, HashSet<i64>)> {
+        let mut expired = vec![];
+        self.typing.retain(|&(chat_id, user_id), typing| {
+            if typing.expires_at > now {
+                return true;
+            }
+            expired.push((chat_id, user_id, std::mem::take(&mut typing.members)));
+            false
+        });
+        expired
+    }
+}
+
+impl AppState {
+    /// Tell the other members of the chat the user is typing, false when the user is
+    /// not a member.
+    pub async fn report_typing(&self, chat_id: i64, user_id: i64) -> Result<bool> {
+        let now = Instant::now();
+        // membership was checked when it was last announced
+        if self.typing.throttle(chat_id, user_id, now) {
+            return Ok(true);
+        }
+
+        let mut members = chat_members(chat_id, &self.pool).await?;
+        if !members.remove(&user_id) {
+            return Ok(false);
+        }
+        self.typing.start(chat_id, user_id, members.clone(), now);
+        self.users
+            .publish(members, Arc::new(AppEvent::Typing { chat_id, user_id }));
+        Ok(true)
+    }
+
+    /// Tell the members the user stopped typing, if the user was.
+    pub fn stop_typing(&self, chat_id: i64, user_id: i64) {
+        if let Some(members) = self.typing.stop(chat_id, user_id) {
+            self.users.publish(
+                members,
+                Arc::new(AppEvent::TypingStopped { chat_id, user_id }),
+            );
+        }
+    }
+
+    /// A user removed from the chat stops typing in it and hears no more of it.
+    pub fn remove_typing_member(&self, chat_id: i64, user_id: i64) {
+        self.stop_typing(chat_id, user_id);
+        self.typing.remove_member(chat_id, user_id);
+    }
+
+    pub fn expire_typing(&self) {
+        for (chat_id, user_id, members) in self.typing.expire(Instant::now()) {
+            self.users.publish(
+                members,
+                Arc::new(AppEvent::TypingStopped { chat_id, user_id }),
+            );
+        }
+    }
+}
+
+/// Report typing for clients which only receive events through sse.
+pub(crate) async fn typing_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(chat_id): Path<i64>,
+) -> StatusCode {
+    match state.report_typing(chat_id, user.id).await {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::FORBIDDEN,
+        Err(e) => {
+            warn!(
+                "failed to report typing of user {} in chat {}: {}",
+                user.id, chat_id, e
+            );
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AppConfig;
+    use futures::StreamExt;
+
+    #[test]
+    fn typing_should_be_throttled() {
+        let registry = TypingRegistry::default();
+        let now = Instant::now();
+        assert!(!registry.throttle(1, 2, now));
+
+        registry.start(1, 2, HashSet::from([1, 3]), now);
+        assert!(registry.throttle(1, 2, now + Duration::from_secs(1)));
+        assert!(!registry.throttle(1, 2, now + TYPING_THROTTLE));
+        // other users and chats are not throttled
+        assert!(!registry.throttle(1, 3, now));
+        assert!(!registry.throttle(2, 2, now));
+    }
+
+    #[test]
+    fn typing_should_expire() {
+        let registry = TypingRegistry::default();
+        let now = Instant::now();
+        registry.start(1, 2, HashSet::from([1, 3]), now);
+        registry.start(1, 3, HashSet::from([1, 2]), now);
+        // still typing keeps it alive
+        registry.throttle(1, 2, now + Duration::from_secs(2));
+
+        let expired = registry.expire(now + TYPING_TIMEOUT);
+        assert_eq!(expired, vec![(1, 3, HashSet::from([1, 2]))]);
+        assert!(registry
+            .expire(now + TYPING_TIMEOUT + Duration::from_secs(1))
+            .is_empty());
+        assert_eq!(
+            registry.expire(now + Duration::from_secs(2) + TYPING_TIMEOUT),
+            vec![(1, 2, HashSet::from([1, 3]))]
+        );
+
+        registry.start(1, 2, HashSet::from([1]), now);
+        assert_eq!(registry.stop(1, 2), Some(HashSet::from([1])));
+        assert_eq!(registry.stop(1, 2), None);
+    }
+
+    #[tokio::test]
+    async fn removed_member_should_leave_typing() -> Result<()> {
+        let state = AppState::new_for_test(AppConfig::load()?)?;
+        let now = Instant::now();
+        state.typing.start(1, 2, HashSet::from([1, 3]), now);
+        state.typing.start(1, 3, HashSet::from([1, 2]), now);
+        state.typing.start(2, 1, HashSet::from([3]), now);
+
+        state.remove_typing_member(1, 3);
+        assert_eq!(state.typing.stop(1, 3), None);
+        assert_eq!(state.typing.stop(1, 2), Some(HashSet::from([1])));
+        // other chats are not affected
+        assert_eq!(state.typing.stop(2, 1), Some(HashSet::from([3])));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stopped_typing_should_be_published() -> Result<()> {
+        let state = AppState::new_for_test(AppConfig::load()?)?;
+        let mut sub = state.users.subscribe(1, None);
+        state
+            .typing
+            .start(1, 2, HashSet::from([1, 3]), Instant::now());
+
+        state.stop_typing(1, 2);
+        assert_eq!(
+            *sub.next().await.unwrap().event,
+            AppEvent::TypingStopped {
+                chat_id: 1,
+                user_id: 2
+            }
+        );
+        // nothing is sent when the user was not typing
+        state.stop_typing(1, 2);
+        state.users.publish(
+            [1],
+            Arc::new(AppEvent::MemberAdded {
+                chat_id: 1,
+                user_id: 4,
+            }),
+        );
+        assert_eq!(
+            *sub.next().await.unwrap().event,
+            AppEvent::MemberAdded {
+                chat_id: 1,
+                user_id: 4
+            }
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,180 @@
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use axum::{
+    extract::{
+        ws::{Message, WebSocket},
+        Query, State, WebSocketUpgrade,
+    },
+    response::Response,
+    Extension,
+};
+use chat_core::{move_read_position, AppEvent, User};
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::time::{sleep_until, Instant};
+use tracing::{info, warn};
+
+use crate::{AppState, UserEvent};
+
+// clients are expected to send a heartbeat well within this period
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct WsParams {
+    last_event_id: Option<u64>,
+}
+
+/// Frames sent by clients.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientFrame {
+    Typing { chat_id: i64 },
+    Heartbeat,
+    Read { chat_id: i64, message_id: i64 },
+}
+
+/// Frames sent to clients, the event with the id to resume from.
+#[derive(Debug, Serialize)]
+struct ServerFrame<'a> {
+    id: u64,
+    #[serde(flatten)]
+    event: &'a AppEvent,
+}
+
+pub(crate) async fn ws_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Query(params): Query<WsParams>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    info!(
+        "User {} connected through websocket, last_event_id: {:?}",
+        user.id, params.last_event_id
+    );
+    ws.on_upgrade(move |socket| handle_socket(socket, state, user, params.last_event_id))
+}
+
+async fn handle_socket(socket: WebSocket, state: AppState, user: User, last_event_id: Option<u64>) {
+    let (mut sender, mut receiver) = socket.split();
+    // shares the registry with sse, and is unregistered when the socket closes
+    let mut subscription = state.users.subscribe(user.id, last_event_id);
+
+    let mut deadline = Instant::now() + HEARTBEAT_TIMEOUT;
+    loop {
+        tokio::select! {
+            event = subscription.next() => {
+                let Some(event) = event else {
+                    break;
+                };
+                if let Err(e) = sender.send(Message::Text(encode(&event))).await {
+                    warn!("failed to send event to user {}: {}", user.id, e);
+                    break;
+                }
+            }
+            msg = receiver.next() => {
+                deadline = Instant::now() + HEARTBEAT_TIMEOUT;
+                let text = match msg {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | None => break,
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => {
+                        warn!("failed to receive frame from user {}: {}", user.id, e);
+                        break;
+                    }
+                };
+                let ret = match serde_json::from_str::<ClientFrame>(&text) {
+                    Ok(frame) => frame.handle(&user, &state).await,
+                    Err(e) => Err(e.into()),
+                };
+                if let Err(e) = ret {
+                    warn!("failed to handle frame {} from user {}: {}", text, user.id, e);
+                }
+            }
+            _ = sleep_until(deadline) => {
+                info!("User {} missed heartbeat, closing", user.id);
+                break;
+            }
+        }
+    }
+
+    info!("User {} disconnected from websocket", user.id);
+}
+
+fn encode(event: &UserEvent) -> String {
+    let frame = ServerFrame {
+        id: event.id,
+        event: &event.event,
+    };
+    // AppEvent only holds plain data, it always serializes
+    serde_json::to_string(&frame).expect("serialize event")
+}
+
+impl ClientFrame {
+    async fn handle(self, user: &User, state: &AppState) -> Result<()> {
+        match self {
+            // any frame keeps the connection alive
+            ClientFrame::Heartbeat => Ok(()),
+            ClientFrame::Typing { chat_id } => {
+                if !state.report_typing(chat_id, user.id).await? {
+                    bail!("user {} is not a member of chat {}", user.id, chat_id);
+                }
+                Ok(())
+            }
+            ClientFrame::Read {
+                chat_id,
+                message_id,
+            } => {
+                move_read_position(&state.pool, chat_id, user.id, message_id).await?;
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn client_frame_should_parse() -> Result<()> {
+        let frame: ClientFrame = serde_json::from_str(r#"{"type": "typing", "chat_id": 1}"#)?;
+        assert_eq!(frame, ClientFrame::Typing { chat_id: 1 });
+
+        let frame: ClientFrame = serde_json::from_str(r#"{"type": "heartbeat"}"#)?;
+        assert_eq!(frame, ClientFrame::Heartbeat);
+
+        let frame: ClientFrame =
+            serde_json::from_str(r#"{"type": "read", "chat_id": 1, "message_id": 10}"#)?;
+        assert_eq!(
+            frame,
+            ClientFrame::Read {
+                chat_id: 1,
+                message_id: 10
+            }
+        );
+
+        assert!(serde_json::from_str::<ClientFrame>(r#"{"type": "typing"}"#).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn server_frame_should_carry_event_id() -> Result<()> {
+        let event = UserEvent::new(
+            42,
+            AppEvent::Typing {
+                chat_id: 1,
+                user_id: 2,
+            },
+        );
+        let frame: serde_json::Value = serde_json::from_str(&encode(&event))?;
+        assert_eq!(
+            frame,
+            serde_json::json!({"id": 42, "event": "typing", "chat_id": 1, "user_id": 2})
+        );
+
+        Ok(())
+    }
+}